@@ -96,9 +96,15 @@ impl<'a, L: 'a + LedgerReader> LedgerSnapshot<'a, L> {
                 //          source.get_tokens(),
                 //          source.get_tokens() - transfer.get_tokens());
 
-                let source_tokens = source.get_tokens() - transfer.get_tokens();
-                let source_op_index = source.get_op_index() + 1;
-                let dest_tokens = destination.get_tokens() + transfer.get_tokens();
+                let source_tokens = try!(source.get_tokens()
+                    .checked_sub(transfer.get_tokens())
+                    .ok_or(IroncError::token_overflow()));
+                let source_op_index = try!(source.get_op_index().checked_add(1)
+                    .ok_or(IroncError::new(&format!(
+                        "Op number of source address {} overflows.", source_pk))));
+                let dest_tokens = try!(destination.get_tokens()
+                    .checked_add(transfer.get_tokens())
+                    .ok_or(IroncError::token_overflow()));
                 source.set_tokens(source_tokens);
                 source.set_op_index(source_op_index);
                 destination.set_tokens(dest_tokens);
@@ -302,3 +308,30 @@ fn test_ledger_snapshot_make_patches() {
     snapshot.set_balance(&pk1, balance_pk1.clone());
     println!("{:?}", snapshot.make_patches());
 }
+
+#[test]
+fn test_ledger_snapshot_add_transfer_overflow() {
+    use std::u64;
+    use crypto::gen_keypair;
+
+    let (pk1, _) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut balance_pk1 = Balance::new();
+    balance_pk1.set_tokens(1000);
+    balance_pk1.set_op_index(0);
+    let mut balance_pk2 = Balance::new();
+    balance_pk2.set_tokens(u64::MAX);
+    balance_pk2.set_op_index(0);
+    let ledger_cell = RefCell::new(HashMap::new());
+    ledger_cell.borrow_mut().insert(pk1.clone(), balance_pk1);
+    ledger_cell.borrow_mut().insert(pk2.clone(), balance_pk2);
+    let ledger = TestLedgerHashMap { ledger: ledger_cell };
+
+    let mut transfer = Transfer::new();
+    transfer.set_source_pk(pk1.0.to_vec());
+    transfer.set_destination_pk(pk2.0.to_vec());
+    transfer.set_tokens(1);
+    transfer.set_op_index(0);
+    let mut snapshot = LedgerSnapshot::new(&ledger);
+    assert_eq!(Err(IroncError::token_overflow()), snapshot.add_transfer(&transfer));
+}
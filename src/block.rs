@@ -4,15 +4,17 @@ use crypto::{HashDigest, PublicKey, SecretKey, Signature,
              gen_keypair, hash_message, sign_message, verify_signed_message};
 use error::{IroncError, IroncResult};
 use ironcoin_pb::{Block, BlockWithDiff, HashedBlock, SignedBlock, Transaction};
-use tx::{TransactionBuilder, TransactionExt};
+use tx::{DEFAULT_CHAIN_ID, TransactionBuilder, TransactionExt};
 
 fn create_genesis_block(staker_pk: &PublicKey, staker_sk: &SecretKey,
-                        tx: Transaction) -> IroncResult<HashedBlock> {
-    if tx.get_commit().get_bounty() != 0 || tx.get_commit().has_bounty_pk() {
-        return Err(IroncError::new(
-            "Transactions must not have a bounty set in a genesis block."));
+                        txs: Vec<Transaction>) -> IroncResult<HashedBlock> {
+    for tx in txs.iter() {
+        if tx.get_commit().get_bounty() != 0 || tx.get_commit().has_bounty_pk() {
+            return Err(IroncError::new(
+                "Transactions must not have a bounty set in a genesis block."));
+        }
+        try!(tx.verify_signatures(DEFAULT_CHAIN_ID));
     }
-    try!(tx.verify_signatures());
     let mut genesis = HashedBlock::new();
     genesis.mut_signed_block().mut_block().set_staker_pk(staker_pk.0.to_vec());
     genesis.mut_signed_block().mut_block().set_previous(
@@ -20,7 +22,9 @@ fn create_genesis_block(staker_pk: &PublicKey, staker_sk: &SecretKey,
     genesis.mut_signed_block().mut_block().set_height(0);
     genesis.mut_signed_block().mut_block().set_timestamp(
         now_utc().to_timespec().sec);
-    genesis.mut_signed_block().mut_block().mut_transactions().push(tx);
+    for tx in txs.into_iter() {
+        genesis.mut_signed_block().mut_block().mut_transactions().push(tx);
+    }
 
     genesis.mut_signed_block().sign(staker_sk);
     genesis.compute_hash();
@@ -42,18 +46,24 @@ impl GenesisBuilder {
         self.transfers.push((destination, tokens));
     }
 
+    // Mints the tokens with one coinbase transaction per destination, in the
+    // order the destinations were first added.
     pub fn build(self) -> HashedBlock {
         let (public_key, secret_key) = gen_keypair();
-        let mut tx_builder = TransactionBuilder::new();
-        let mut op_num = 0u32;
+        let mut minted = Vec::<(PublicKey, u64)>::new();
         for (destination, tokens) in self.transfers.into_iter() {
-            tx_builder.add_transfer(
-                &secret_key, &public_key, &destination, tokens, op_num);
-            op_num += 1;
+            match minted.iter().position(|entry| entry.0 == destination) {
+                Some(index) => {
+                    minted[index].1 = minted[index].1.checked_add(tokens)
+                        .expect("Genesis tokens for a destination overflow.");
+                },
+                None => minted.push((destination, tokens))
+            }
         }
-        let genesis_tx = tx_builder.build().unwrap();
-        assert!(genesis_tx.verify_signatures().is_ok());
-        create_genesis_block(&public_key, &secret_key, genesis_tx).unwrap()
+        let genesis_txs = minted.iter().map(|&(ref destination, tokens)| {
+            TransactionBuilder::coinbase(destination, tokens).unwrap()
+        }).collect();
+        create_genesis_block(&public_key, &secret_key, genesis_txs).unwrap()
     }
 }
 
@@ -119,7 +129,14 @@ impl HashedBlockExt for HashedBlock {
         try!(self.verify_hash());
         try!(self.get_signed_block().verify_signature());
         let txes = self.get_block().get_transactions();
-        for tx in txes { try!(tx.verify_signatures()); }
+        for tx in txes {
+            if tx.is_coinbase() && self.get_height() != 0 {
+                return Err(IroncError::new("Coinbase transactions are only \
+                                            valid in the genesis block."));
+            }
+            let timestamp = self.get_block().get_timestamp() as u64;
+            try!(tx.verify_signatures_at(DEFAULT_CHAIN_ID, timestamp));
+        }
         Ok(())
     }
 }
@@ -189,25 +206,54 @@ use crypto::{hash};
 #[test]
 fn test_create_genesis_empty() {
     let (pk, sk) = gen_keypair();
-    let tx = Transaction::new();
-    let genesis = create_genesis_block(&pk, &sk, tx).unwrap();
+    let genesis = create_genesis_block(&pk, &sk, vec![]).unwrap();
     genesis.verify().unwrap();
     assert!(0 == genesis.get_block().get_height());
 }
 
+#[test]
+fn test_create_genesis_with_coinbase() {
+    let (pk, sk) = gen_keypair();
+    let tx = TransactionBuilder::coinbase(&pk, 1000).unwrap();
+    let genesis = create_genesis_block(&pk, &sk, vec![tx]).unwrap();
+    genesis.verify().unwrap();
+}
+
+#[test]
+fn test_genesis_builder_mints_with_coinbase() {
+    let (pk1, _) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 10);
+    builder.add_transfer(pk2.clone(), 22);
+    builder.add_transfer(pk1.clone(), 5);
+    let genesis = builder.build();
+    genesis.verify().unwrap();
+
+    let txs = genesis.get_block().get_transactions();
+    assert_eq!(2, txs.len());
+    for (tx, &(ref destination, tokens)) in
+        txs.iter().zip([(pk1, 15u64), (pk2, 22u64)].iter()) {
+        assert!(tx.is_coinbase());
+        let transfer = &tx.get_commit().get_transfers()[0];
+        assert_eq!(&destination.0[..], transfer.get_destination_pk());
+        assert_eq!(tokens, transfer.get_tokens());
+    }
+}
+
 #[test]
 fn test_create_genesis_with_invalid_tx() {
     let (pk1, sk1) = gen_keypair();
     let (pk2, sk2) = gen_keypair();
 
     let mut tx_builder = TransactionBuilder::new();
-    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0);
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap();
     let maybe_tx = tx_builder.build();
     assert!(maybe_tx.is_ok());
     let mut tx = maybe_tx.unwrap();
-    assert!(create_genesis_block(&pk1, &sk1, tx.clone()).is_ok());
+    assert!(create_genesis_block(&pk1, &sk1, vec![tx.clone()]).is_ok());
     tx.clear_signatures();
-    assert!(create_genesis_block(&pk1, &sk1, tx).is_err());
+    assert!(create_genesis_block(&pk1, &sk1, vec![tx]).is_err());
 }
 
 #[test]
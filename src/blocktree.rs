@@ -17,19 +17,19 @@ fn make_genesis_block_diff(genesis: HashedBlock)
                            -> IroncResult<BlockWithDiff>
 {
     try!(genesis.verify());
-    let num_tx = genesis.get_block().get_transactions().len();
-    if num_tx == 0 {
-        Ok(BlockWithDiff::new())
-    } else if num_tx == 1 {
-        let mut cache = HashMap::<PublicKey, u64>::new();
-        let commit =
-            genesis.get_block().get_transactions()[0].get_commit().clone();
-        for transfer in commit.get_transfers() {
+    if genesis.get_block().get_transactions().is_empty() {
+        return Ok(BlockWithDiff::new());
+    }
+    let mut cache = HashMap::<PublicKey, u64>::new();
+    for tx in genesis.get_block().get_transactions().iter() {
+        for transfer in tx.get_commit().get_transfers() {
             let destination =
                 try!(PublicKey::from_slice(transfer.get_destination_pk()));
             match cache.entry(destination) {
                 hash_map::Entry::Occupied(mut tokens) => {
-                    let new_balance = tokens.get() + transfer.get_tokens();
+                    let new_balance = try!(tokens.get()
+                        .checked_add(transfer.get_tokens())
+                        .ok_or(IroncError::token_overflow()));
                     tokens.insert(new_balance);
                 },
                 hash_map::Entry::Vacant(tokens) => {
@@ -37,28 +37,25 @@ fn make_genesis_block_diff(genesis: HashedBlock)
                 }
             };
         }
-        let mut block_diff = BlockWithDiff::new();
-        block_diff.set_hashed_block(genesis);
-        for (address, tokens) in cache.into_iter() {
-            let mut before = Balance::new();
-            before.set_tokens(0u64);
-            before.set_op_index(0u32);
-
-            let mut after = Balance::new();
-            after.set_tokens(tokens);
-            after.set_op_index(0u32);
-
-            let mut patch = BalancePatch::new();
-            patch.set_public_key(address.0.to_vec());
-            patch.set_before(before);
-            patch.set_after(after);
-            block_diff.mut_diff().push(patch);
-        }
-        Ok(block_diff)
-    } else {
-         Err(IroncError::new(
-             "Genesis block must contain at most 1 transaction."))
     }
+    let mut block_diff = BlockWithDiff::new();
+    block_diff.set_hashed_block(genesis);
+    for (address, tokens) in cache.into_iter() {
+        let mut before = Balance::new();
+        before.set_tokens(0u64);
+        before.set_op_index(0u32);
+
+        let mut after = Balance::new();
+        after.set_tokens(tokens);
+        after.set_op_index(0u32);
+
+        let mut patch = BalancePatch::new();
+        patch.set_public_key(address.0.to_vec());
+        patch.set_before(before);
+        patch.set_after(after);
+        block_diff.mut_diff().push(patch);
+    }
+    Ok(block_diff)
 }
 
 fn format_balance_key(address: &PublicKey) -> String {
@@ -342,6 +339,7 @@ fn test_make_genesis_patch_non_unqiue_dest() {
 
 #[test]
 fn test_genesis_builder() {
+    use tx::TransactionExt;
     let (pk1, sk1) = gen_keypair();
     let (pk2, sk2) = gen_keypair();
     let (pk3, sk3) = gen_keypair();
@@ -353,8 +351,8 @@ fn test_genesis_builder() {
 
     assert!(genesis.verify().is_ok());
     let txs = genesis.get_block().get_transactions();
-    assert!(1 == txs.len());
-    assert!(3 == txs[0].get_commit().get_transfers().len());
+    assert!(3 == txs.len());
+    assert!(txs.iter().all(|tx| tx.is_coinbase()));
 }
 
 #[test]
@@ -426,8 +424,8 @@ fn test_blocktree_insert_and_snapshot_at() {
     assert!(genesis == blocktree.get_genesis().unwrap());
 
     let mut tx_builder1 = TransactionBuilder::new();
-    tx_builder1.add_transfer(&sk1, &pk1, &pk2, 11, 0);
-    tx_builder1.add_transfer(&sk2, &pk2, &pk1, 1, 0);
+    tx_builder1.add_transfer(&sk1, &pk1, &pk2, 11, 0).unwrap();
+    tx_builder1.add_transfer(&sk2, &pk2, &pk1, 1, 0).unwrap();
     let tx1 = tx_builder1.build().unwrap();
     let mut block1 = HashedBlock::new();
     block1.mut_signed_block().mut_block().mut_transactions().push(tx1);
@@ -447,7 +445,7 @@ fn test_blocktree_insert_and_snapshot_at() {
     assert!(0 == blocktree.get_balance(&pk3).unwrap().get_op_index());
 
     let mut tx_builder2 = TransactionBuilder::new();
-    tx_builder2.add_transfer(&sk2, &pk2, &pk3, 10, 1);
+    tx_builder2.add_transfer(&sk2, &pk2, &pk3, 10, 1).unwrap();
     let tx2 = tx_builder2.build().unwrap();
     let mut block2 = HashedBlock::new();
     block2.mut_signed_block().mut_block().mut_transactions().push(tx2);
@@ -464,8 +462,8 @@ fn test_blocktree_insert_and_snapshot_at() {
     assert!(0 == blocktree.get_balance(&pk3).unwrap().get_op_index());
 
     let mut tx_builder3 = TransactionBuilder::new();
-    tx_builder3.add_transfer(&sk1, &pk1, &pk3, 100, 0);
-    tx_builder3.add_transfer(&sk2, &pk2, &pk1, 7, 0);
+    tx_builder3.add_transfer(&sk1, &pk1, &pk3, 100, 0).unwrap();
+    tx_builder3.add_transfer(&sk2, &pk2, &pk1, 7, 0).unwrap();
     let tx3 = tx_builder3.build().unwrap();
     let mut block3 = HashedBlock::new();
     block3.mut_signed_block().mut_block().mut_transactions().push(tx3);
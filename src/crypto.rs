@@ -1,16 +1,22 @@
 use std::cmp::Ordering;
 use std::error::Error;
 use std::fmt;
-use std::hash::{Hash, Hasher};
+use std::hash::{Hash, Hasher as StdHasher};
+use std::intrinsics::volatile_set_memory;
+use std::io;
 use std::slice::bytes::copy_memory;
+use std::str::FromStr;
 
+use libc::{c_int, c_uchar, c_ulonglong};
 use protobuf::MessageStatic;
-use rustc_serialize::base64::{self, ToBase64};
-use sodiumoxide::crypto::hash::sha512::{self, HASHBYTES};
+use rustc_serialize::base64::{self, FromBase64, ToBase64};
+use rustc_serialize::hex::{FromHex, ToHex};
+use sodiumoxide::crypto::hash::sha256::{self, HASHBYTES};
+use sodiumoxide::randombytes::randombytes_into;
 use sodiumoxide::crypto::sign::ed25519::{
-    self, PUBLICKEYBYTES, SECRETKEYBYTES, SIGNATUREBYTES};
+    self, PUBLICKEYBYTES, SECRETKEYBYTES, SEEDBYTES, SIGNATUREBYTES};
 
-use error::{IroncError, IroncResult};
+use error::{IroncError, IroncErrorKind, IroncResult};
 
 // HashDigest:
 
@@ -74,7 +80,7 @@ impl PartialEq for HashDigest {
 impl Eq for HashDigest {}
 
 impl Hash for HashDigest {
-    fn hash<H: Hasher>(&self, state: &mut H) { state.write(self.as_slice()); }
+    fn hash<H: StdHasher>(&self, state: &mut H) { state.write(self.as_slice()); }
 }
 
 impl PartialOrd for HashDigest {
@@ -109,8 +115,69 @@ impl fmt::Display for HashDigest {
     }
 }
 
+// All hashing in ironcoin (block hashes, txids, addresses, Merkle trees and key
+// derivation) goes through `hash`, so every component agrees on the digest:
+// a 32 byte SHA-256.
 pub fn hash(bytes: &[u8]) -> HashDigest {
-    HashDigest(sha512::hash(bytes).0)
+    HashDigest(sha256::hash(bytes).0)
+}
+
+// sodiumoxide only wraps the one-shot sha256, so the incremental interface
+// comes straight from libsodium, which it already links.
+#[repr(C)]
+struct Sha256State {
+    state: [u32; 8],
+    count: u64,
+    buf: [u8; 64]
+}
+
+#[link(name = "sodium")]
+extern {
+    fn crypto_hash_sha256_init(state: *mut Sha256State) -> c_int;
+    fn crypto_hash_sha256_update(state: *mut Sha256State, input: *const c_uchar,
+                                 input_len: c_ulonglong) -> c_int;
+    fn crypto_hash_sha256_final(state: *mut Sha256State, out: *mut c_uchar)
+                                -> c_int;
+}
+
+// Computes `hash` over input fed in pieces, e.g. with io::copy from a file,
+// without holding all of it in memory.
+pub struct Hasher {
+    state: Sha256State
+}
+
+impl Hasher {
+    pub fn new() -> Hasher {
+        let mut hasher = Hasher {
+            state: Sha256State { state: [0; 8], count: 0, buf: [0; 64] }
+        };
+        unsafe { crypto_hash_sha256_init(&mut hasher.state); }
+        hasher
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        unsafe {
+            crypto_hash_sha256_update(&mut self.state, bytes.as_ptr(),
+                                      bytes.len() as c_ulonglong);
+        }
+    }
+
+    pub fn finalize(mut self) -> HashDigest {
+        let mut digest = HashDigest([0; HASHBYTES]);
+        unsafe {
+            crypto_hash_sha256_final(&mut self.state, digest.0.as_mut_ptr());
+        }
+        digest
+    }
+}
+
+impl io::Write for Hasher {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.update(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
 }
 
 // PublicKey:
@@ -132,6 +199,38 @@ impl PublicKey {
             Ok(public_key)
         }
     }
+
+    pub fn from_hex(encoded: &str) -> IroncResult<PublicKey> {
+        PublicKey::from_slice(&try!(encoded.from_hex()))
+    }
+
+    pub fn to_hex(&self) -> String { self.0.to_hex() }
+}
+
+impl PublicKey {
+    // Addresses are base58(public key || checksum), where the checksum is the
+    // first ADDRESS_CHECKSUM_BYTES bytes of the hash of the public key.
+    pub fn to_address(&self) -> String {
+        let mut payload = self.0.to_vec();
+        payload.push_all(&hash(&self.0).0[..ADDRESS_CHECKSUM_BYTES]);
+        to_base58(&payload)
+    }
+
+    pub fn from_address(address: &str) -> IroncResult<PublicKey> {
+        let payload = try!(from_base58(address));
+        if payload.len() != PUBLICKEYBYTES + ADDRESS_CHECKSUM_BYTES {
+            return Err(IroncError::with_kind(IroncErrorKind::Decode, &format!(
+                "Invalid address length {} != {} (required)", payload.len(),
+                PUBLICKEYBYTES + ADDRESS_CHECKSUM_BYTES)));
+        }
+        let public_key = try!(PublicKey::from_slice(&payload[..PUBLICKEYBYTES]));
+        let checksum = &hash(&public_key.0).0[..ADDRESS_CHECKSUM_BYTES];
+        if &payload[PUBLICKEYBYTES..] != checksum {
+            return Err(IroncError::with_kind(IroncErrorKind::Decode, &format!(
+                "Invalid checksum for address {}", address)));
+        }
+        Ok(public_key)
+    }
 }
 
 impl fmt::Display for PublicKey {
@@ -140,6 +239,90 @@ impl fmt::Display for PublicKey {
     }
 }
 
+// Parses the base64 encoding written by Display, falling back to an address.
+impl FromStr for PublicKey {
+    type Err = IroncError;
+
+    fn from_str(encoded: &str) -> IroncResult<PublicKey> {
+        match encoded.from_base64() {
+            Ok(ref bytes) if bytes.len() == PUBLICKEYBYTES =>
+                PublicKey::from_slice(bytes),
+            _ => PublicKey::from_address(encoded).map_err(|_| IroncError::new(
+                &format!("Could not parse \"{}\" as a public key.", encoded)))
+        }
+    }
+}
+
+// Multisig accounts:
+
+// The account controlled by `threshold` of `signers` is identified by the
+// first PUBLICKEYBYTES bytes of hash(threshold as 4 LE bytes || signers...),
+// so the order of the signers matters. Nobody knows a secret key for it.
+pub fn multisig_account(threshold: u32, signers: &[PublicKey]) -> PublicKey {
+    let mut account_bytes = vec![
+        (threshold & 0xff) as u8, ((threshold >> 8) & 0xff) as u8,
+        ((threshold >> 16) & 0xff) as u8, ((threshold >> 24) & 0xff) as u8];
+    for signer in signers.iter() { account_bytes.push_all(&signer.0); }
+    PublicKey::from_slice(&hash(&account_bytes).0[..PUBLICKEYBYTES]).unwrap()
+}
+
+// Base58:
+
+pub const ADDRESS_CHECKSUM_BYTES: usize = 4;
+
+const BASE58_ALPHABET: &'static [u8] =
+    b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+pub fn to_base58(bytes: &[u8]) -> String {
+    // Base58 digits are accumulated least significant first.
+    let mut digits: Vec<u8> = vec![];
+    for byte in bytes.iter() {
+        let mut carry = *byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let mut encoded = String::new();
+    for _ in bytes.iter().take_while(|byte| **byte == 0) { encoded.push('1'); }
+    for digit in digits.iter().rev() {
+        encoded.push(BASE58_ALPHABET[*digit as usize] as char);
+    }
+    encoded
+}
+
+pub fn from_base58(encoded: &str) -> IroncResult<Vec<u8>> {
+    // Decoded bytes are accumulated least significant first.
+    let mut bytes: Vec<u8> = vec![];
+    for character in encoded.chars() {
+        let mut carry = match BASE58_ALPHABET.iter().position(
+            |symbol| *symbol as char == character) {
+            Some(value) => value as u32,
+            None => return Err(IroncError::with_kind(IroncErrorKind::Decode,
+                &format!("Invalid base58 character '{}'", character)))
+        };
+        for byte in bytes.iter_mut() {
+            carry += (*byte as u32) * 58;
+            *byte = (carry & 0xff) as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push((carry & 0xff) as u8);
+            carry >>= 8;
+        }
+    }
+    for _ in encoded.chars().take_while(|character| *character == '1') {
+        bytes.push(0);
+    }
+    bytes.reverse();
+    Ok(bytes)
+}
+
 // SecretKey:
 
 pub struct SecretKey(pub [u8; SECRETKEYBYTES]);
@@ -158,10 +341,24 @@ impl SecretKey {
             Ok(secret_key)
         }
     }
+
+    // Debug and Display never show the key; use these when the actual value
+    // is needed, e.g. for a wallet export.
+    pub fn from_base64(encoded: &str) -> IroncResult<SecretKey> {
+        SecretKey::from_slice(&try!(encoded.from_base64()))
+    }
+
+    pub fn to_base64(&self) -> String { self.0.to_base64(base64::STANDARD) }
+
+    pub fn from_hex(encoded: &str) -> IroncResult<SecretKey> {
+        SecretKey::from_slice(&try!(encoded.from_hex()))
+    }
+
+    pub fn to_hex(&self) -> String { self.0.to_hex() }
 }
 
 impl Drop for SecretKey {
-    fn drop(&mut self) { copy_memory(&mut self.0, &[0; SECRETKEYBYTES]); }
+    fn drop(&mut self) { zero_memory(&mut self.0); }
 }
 
 impl Clone for SecretKey {
@@ -170,33 +367,92 @@ impl Clone for SecretKey {
 
 impl PartialEq for SecretKey {
     fn eq(&self, other: &SecretKey) -> bool {
-        self.as_slice() == other.as_slice()
+        ct_eq(self.as_slice(), other.as_slice())
     }
 }
 
 impl Eq for SecretKey {}
 
 impl Hash for SecretKey {
-    fn hash<H: Hasher>(&self, state: &mut H) { state.write(self.as_slice()); }
+    fn hash<H: StdHasher>(&self, state: &mut H) { state.write(self.as_slice()); }
 }
 
 impl fmt::Debug for SecretKey {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        write!(formatter, "{:?}", self.as_slice())
+        write!(formatter, "SecretKey(<redacted>)")
     }
 }
 
 impl fmt::Display for SecretKey {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        write!(formatter, "{}", self.as_slice().to_base64(base64::STANDARD))
+        write!(formatter, "<redacted>")
     }
 }
 
+// Overwrites secret material with zeros. The write is volatile so that it
+// isn't optimised away as a dead store right before the memory is freed.
+pub fn zero_memory(bytes: &mut [u8]) {
+    unsafe { volatile_set_memory(bytes.as_mut_ptr(), 0, bytes.len()); }
+}
+
+// Compares byte strings in time that depends only on their lengths, for
+// secret material (secret keys, and anything derived from them) where an
+// early exit on the first differing byte would leak its position. Public
+// keys, hashes and signatures are public and are compared with ==. Wallet
+// decryption needs no comparison here: secretbox::open checks the MAC in
+// constant time itself.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() { return false; }
+    let mut diff = 0u8;
+    for (byte_a, byte_b) in a.iter().zip(b.iter()) { diff |= *byte_a ^ *byte_b; }
+    diff == 0
+}
+
 pub fn gen_keypair() -> (PublicKey, SecretKey) {
     let (pk, sk) = ed25519::gen_keypair();
     (PublicKey(pk.0), SecretKey(sk.0))
 }
 
+pub fn derive_public_key(secret_key: &SecretKey) -> PublicKey {
+    // An ed25519 secret key starts with the seed it was generated from.
+    let mut seed = ed25519::Seed([0; SEEDBYTES]);
+    copy_memory(&mut seed.0, &secret_key.0[..SEEDBYTES]);
+    let (pk, _) = ed25519::keypair_from_seed(&seed);
+    PublicKey(pk.0)
+}
+
+// Deterministic keys:
+
+pub const MASTER_SEED_BYTES: usize = 32;
+
+pub fn gen_master_seed() -> [u8; MASTER_SEED_BYTES] {
+    let mut seed = [0; MASTER_SEED_BYTES];
+    randombytes_into(&mut seed);
+    seed
+}
+
+// The same seed always gives the same keypair, e.g. for reproducible tests.
+pub fn keypair_from_seed(seed: &[u8; SEEDBYTES]) -> (PublicKey, SecretKey) {
+    let (pk, sk) = ed25519::keypair_from_seed(&ed25519::Seed(*seed));
+    (PublicKey(pk.0), SecretKey(sk.0))
+}
+
+// The child key at `index` uses the first SEEDBYTES bytes of
+// hash(seed || index as 4 LE bytes) as its ed25519 seed (ed25519 clamps the
+// scalar derived from it), so the same master seed always gives the same keys.
+pub fn derive_key(seed: &[u8], index: u32) -> (PublicKey, SecretKey) {
+    let mut input = seed.to_vec();
+    input.push_all(&[
+        (index & 0xff) as u8, ((index >> 8) & 0xff) as u8,
+        ((index >> 16) & 0xff) as u8, ((index >> 24) & 0xff) as u8]);
+    let mut child_seed = ed25519::Seed([0; SEEDBYTES]);
+    copy_memory(&mut child_seed.0, &hash(&input).0[..SEEDBYTES]);
+    zero_memory(&mut input);
+    let (pk, sk) = ed25519::keypair_from_seed(&child_seed);
+    zero_memory(&mut child_seed.0);
+    (PublicKey(pk.0), SecretKey(sk.0))
+}
+
 // Signature:
 
 pub struct Signature(pub [u8; SIGNATUREBYTES]);
@@ -230,7 +486,7 @@ impl PartialEq for Signature {
 impl Eq for Signature {}
 
 impl Hash for Signature {
-    fn hash<H: Hasher>(&self, state: &mut H) { state.write(self.as_slice()); }
+    fn hash<H: StdHasher>(&self, state: &mut H) { state.write(self.as_slice()); }
 }
 
 impl fmt::Debug for Signature {
@@ -254,7 +510,23 @@ pub fn verify_signature(public_key: &PublicKey, message: &[u8],
     if ed25519::verify_detached(&ed25519::Signature(signature.0),
                                 message, &ed25519::PublicKey(public_key.0)) {
         Ok(())
-    } else { Err(IroncError::new("Invalid signature.")) }
+    } else { Err(IroncError::invalid_signature("Invalid signature.")) }
+}
+
+// Verifies (public key, message, signature) triples, failing with the index of
+// the first invalid one. libsodium (and so sodiumoxide) has no ed25519 batch
+// verification, so for now this costs the same as calling verify_signature in
+// a loop; callers should still prefer it so they benefit once one is exposed.
+pub fn verify_batch<'a>(items: &[(PublicKey, &'a [u8], Signature)])
+                        -> IroncResult<()> {
+    for (index, &(ref public_key, message, ref signature)) in
+        items.iter().enumerate() {
+        if verify_signature(public_key, message, signature).is_err() {
+            return Err(IroncError::invalid_signature(&format!(
+                "Signature {} in the batch is invalid.", index)));
+        }
+    }
+    Ok(())
 }
 
 // Utilities for crypto on protobufs:
@@ -358,6 +630,139 @@ fn test_digest_ord() {
     }
 }
 
+#[test]
+fn test_hash() {
+    assert_eq!(32, hash(b"ironcoin").0.len());
+    assert_eq!("47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=",
+               format!("{}", hash(b"")));
+    assert!(hash(b"ironcoin") != hash(b"ironcoin."));
+}
+
+#[test]
+fn test_hasher() {
+    use std::io::Write;
+    let mut hasher = Hasher::new();
+    hasher.update(b"iron");
+    hasher.write_all(b"coin").unwrap();
+    assert_eq!(hash(b"ironcoin"), hasher.finalize());
+    assert_eq!(hash(b""), Hasher::new().finalize());
+}
+
+#[test]
+fn test_derive_public_key() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    assert_eq!(pk1, derive_public_key(&sk1));
+    assert!(pk2 != derive_public_key(&sk1));
+}
+
+#[test]
+fn test_keypair_from_seed() {
+    let (pk1, sk1) = keypair_from_seed(&[7; SEEDBYTES]);
+    let (pk2, sk2) = keypair_from_seed(&[7; SEEDBYTES]);
+    assert_eq!(pk1, pk2);
+    assert_eq!(sk1, sk2);
+    assert_eq!(pk1, derive_public_key(&sk1));
+    assert!(pk1 != keypair_from_seed(&[8; SEEDBYTES]).0);
+}
+
+#[test]
+fn test_derive_key() {
+    let seed = gen_master_seed();
+    let (pk0, sk0) = derive_key(&seed, 0);
+    assert_eq!((pk0.clone(), sk0.clone()), derive_key(&seed, 0));
+    assert_eq!(pk0, derive_public_key(&sk0));
+
+    let (pk1, _) = derive_key(&seed, 1);
+    assert!(pk0 != pk1);
+    let (other_pk0, _) = derive_key(&gen_master_seed(), 0);
+    assert!(pk0 != other_pk0);
+}
+
+#[test]
+fn test_base58_round_trip() {
+    assert_eq!("", to_base58(b""));
+    assert_eq!("1112", to_base58(&[0, 0, 0, 1]));
+    assert_eq!("5Q", to_base58(&[255]));
+    for bytes in [b"".to_vec(), vec![0, 0, 7, 255], b"hello world".to_vec()].iter() {
+        assert_eq!(*bytes, from_base58(&to_base58(bytes)).unwrap());
+    }
+    assert!(from_base58("0OIl").is_err());
+}
+
+#[test]
+fn test_zero_memory() {
+    let mut bytes = [1u8, 2, 3, 4];
+    zero_memory(&mut bytes[1..]);
+    assert_eq!([1u8, 0, 0, 0], bytes);
+}
+
+#[test]
+fn test_secret_key_is_redacted() {
+    let (_, sk) = gen_keypair();
+    assert_eq!("SecretKey(<redacted>)", format!("{:?}", sk));
+    assert_eq!("<redacted>", format!("{}", sk));
+    assert_eq!(sk, SecretKey::from_base64(&sk.to_base64()).unwrap());
+    assert!(SecretKey::from_base64("c2hvcnQ=").is_err());
+}
+
+#[test]
+fn test_key_hex_round_trip() {
+    let (pk, sk) = gen_keypair();
+    assert_eq!(2 * PUBLICKEYBYTES, pk.to_hex().len());
+    assert_eq!(pk, PublicKey::from_hex(&pk.to_hex()).unwrap());
+    assert_eq!(sk, SecretKey::from_hex(&sk.to_hex()).unwrap());
+
+    let odd_length = &pk.to_hex()[1..];
+    assert_eq!(IroncErrorKind::Decode,
+               PublicKey::from_hex(odd_length).unwrap_err().kind());
+    let not_hex = pk.to_hex().replace("a", "x").replace("0", "x");
+    assert_eq!(IroncErrorKind::Decode,
+               PublicKey::from_hex(&not_hex).unwrap_err().kind());
+}
+
+#[test]
+fn test_public_key_address() {
+    let (pk, _) = gen_keypair();
+    let address = pk.to_address();
+    assert_eq!(pk, PublicKey::from_address(&address).unwrap());
+
+    let mut bad_checksum = pk.0.to_vec();
+    bad_checksum.push_all(&[0, 0, 0, 0]);
+    assert_eq!(Err(IroncError::with_kind(IroncErrorKind::Decode, &format!(
+        "Invalid checksum for address {}", to_base58(&bad_checksum)))),
+        PublicKey::from_address(&to_base58(&bad_checksum)));
+    assert_eq!(IroncErrorKind::Decode,
+               PublicKey::from_address(&to_base58(&pk.0)).unwrap_err().kind());
+    assert_eq!(IroncErrorKind::Decode,
+               PublicKey::from_address("0OIl").unwrap_err().kind());
+}
+
+#[test]
+fn test_verify_batch() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let (msg1, msg2): (&[u8], &[u8]) = (b"first", b"second");
+    let mut items = vec![(pk1.clone(), msg1, sign(&sk1, msg1)),
+                         (pk2.clone(), msg2, sign(&sk2, msg2))];
+    assert!(verify_batch(&[]).is_ok());
+    assert!(verify_batch(&items).is_ok());
+
+    items.push((pk1, msg2, sign(&sk2, msg2)));
+    assert_eq!(Err(IroncError::invalid_signature(
+        "Signature 2 in the batch is invalid.")), verify_batch(&items));
+}
+
+#[test]
+fn test_public_key_from_str() {
+    let (pk, _) = gen_keypair();
+    assert_eq!(pk, format!("{}", pk).parse::<PublicKey>().unwrap());
+    assert_eq!(pk, pk.to_address().parse::<PublicKey>().unwrap());
+    assert_eq!(Err(IroncError::new(
+        "Could not parse \"c2hvcnQ=\" as a public key.")),
+        "c2hvcnQ=".parse::<PublicKey>());
+}
+
 // #[test]
 // fn test_digest_encoding() {
 //     let hash1 = hash(b"hello world2");
@@ -394,3 +799,17 @@ fn test_digest_ord() {
 //         }
 //     }
 // }
+
+#[test]
+fn test_ct_eq() {
+    assert!(ct_eq(b"", b""));
+    assert!(ct_eq(b"secret", b"secret"));
+    assert!(!ct_eq(b"secret", b"secreT"));
+    assert!(!ct_eq(b"Secret", b"secret"));
+    assert!(!ct_eq(b"secret", b"secrets"));
+
+    let (_, sk1) = gen_keypair();
+    let (_, sk2) = gen_keypair();
+    assert_eq!(sk1, sk1.clone());
+    assert!(sk1 != sk2);
+}
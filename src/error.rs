@@ -7,15 +7,65 @@ use rustc_serialize;
 
 pub type IroncResult<Msg> = Result<Msg, IroncError>;
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IroncErrorKind {
+    Io,
+    Protobuf,
+    Crypto,
+    MissingKey,
+    InvalidSignature,
+    Decode,
+    Other
+}
+
+#[derive(Debug)]
 pub struct IroncError {
-    description: String
+    kind: IroncErrorKind,
+    description: String,
+    cause: Option<Box<Error + Send>>
 }
 
 impl IroncError {
     pub fn new(description: &str) -> IroncError {
-        IroncError { description: String::from_str(description) }
+        IroncError::with_kind(IroncErrorKind::Other, description)
+    }
+
+    pub fn with_kind(kind: IroncErrorKind, description: &str) -> IroncError {
+        IroncError {
+            kind: kind,
+            description: String::from_str(description),
+            cause: None
+        }
+    }
+
+    pub fn with_cause<Err: Error + Send + 'static>(kind: IroncErrorKind,
+                                                   cause: Err) -> IroncError {
+        IroncError {
+            kind: kind,
+            description: String::from_str(cause.description()),
+            cause: Some(Box::new(cause))
+        }
     }
+
+    pub fn crypto(description: &str) -> IroncError {
+        IroncError::with_kind(IroncErrorKind::Crypto, description)
+    }
+
+    pub fn invalid_signature(description: &str) -> IroncError {
+        IroncError::with_kind(IroncErrorKind::InvalidSignature, description)
+    }
+
+    pub fn missing_key(description: &str) -> IroncError {
+        IroncError::with_kind(IroncErrorKind::MissingKey, description)
+    }
+
+    // Token amounts are summed with checked arithmetic and fail with this
+    // error, as silently wrapping around would create or destroy tokens.
+    pub fn token_overflow() -> IroncError {
+        IroncError::new("Token amount overflow.")
+    }
+
+    pub fn kind(&self) -> IroncErrorKind { self.kind }
 }
 
 impl Display for IroncError {
@@ -27,9 +77,24 @@ impl Display for IroncError {
 impl Error for IroncError {
     fn description(&self) -> &str { &self.description }
 
-    fn cause(&self) -> Option<&Error> { None }
+    fn cause(&self) -> Option<&Error> {
+        match self.cause {
+            Some(ref cause) => Some(&**cause),
+            None => None
+        }
+    }
+}
+
+// The underlying error is only context, two errors are equal if they have the
+// same kind and description.
+impl PartialEq for IroncError {
+    fn eq(&self, other: &IroncError) -> bool {
+        self.kind == other.kind && self.description == other.description
+    }
 }
 
+impl Eq for IroncError {}
+
 // TODO: Use something like below (albeit with a IroncErrorTrait)
 //       once Rust gets negative trait bounds
 //
@@ -41,26 +106,73 @@ impl Error for IroncError {
 //     }
 // }
 
-trait ConvertToIroncError: Error {}
-impl ConvertToIroncError for protobuf::error::ProtobufError {}
-impl ConvertToIroncError for ::std::io::Error {}
-impl ConvertToIroncError for rustc_serialize::json::EncoderError {}
-impl ConvertToIroncError for rustc_serialize::base64::FromBase64Error {}
+trait ConvertToIroncError: Error + Send + 'static {
+    fn kind() -> IroncErrorKind;
+}
+
+impl ConvertToIroncError for protobuf::error::ProtobufError {
+    fn kind() -> IroncErrorKind { IroncErrorKind::Protobuf }
+}
+
+impl ConvertToIroncError for ::std::io::Error {
+    fn kind() -> IroncErrorKind { IroncErrorKind::Io }
+}
+
+impl ConvertToIroncError for rustc_serialize::json::EncoderError {
+    fn kind() -> IroncErrorKind { IroncErrorKind::Other }
+}
+
+impl ConvertToIroncError for rustc_serialize::json::DecoderError {
+    fn kind() -> IroncErrorKind { IroncErrorKind::Decode }
+}
+
+impl ConvertToIroncError for rustc_serialize::base64::FromBase64Error {
+    fn kind() -> IroncErrorKind { IroncErrorKind::Decode }
+}
+
+impl ConvertToIroncError for rustc_serialize::hex::FromHexError {
+    fn kind() -> IroncErrorKind { IroncErrorKind::Decode }
+}
 
 impl<Err: ConvertToIroncError> FromError<Err> for IroncError {
     fn from_error(err: Err) -> IroncError {
-        IroncError { description: String::from_str(err.description()) }
+        IroncError::with_cause(<Err as ConvertToIroncError>::kind(), err)
     }
 }
 
 impl FromError<nanomsg::NanoError> for IroncError {
     fn from_error(err: nanomsg::NanoError) -> IroncError {
-        IroncError { description: String::from_str(err.description) }
+        IroncError::with_kind(IroncErrorKind::Io, &err.description)
     }
 }
 
 impl<'a> FromError<&'a str> for IroncError {
-    fn from_error(err: &'a str) -> IroncError {
-        IroncError { description: String::from_str(err) }
-    }
+    fn from_error(err: &'a str) -> IroncError { IroncError::new(err) }
+}
+
+/*****  Tests  *****/
+
+#[test]
+fn test_from_error_sets_kind_and_cause() {
+    use std::io;
+    use rustc_serialize::base64::FromBase64;
+
+    let io_err: IroncError = FromError::from_error(
+        io::Error::new(io::ErrorKind::NotFound, "no such file", None));
+    assert_eq!(IroncErrorKind::Io, io_err.kind());
+    assert!(io_err.cause().is_some());
+
+    let proto_err: IroncError = FromError::from_error(
+        protobuf::parse_from_bytes::<::ironcoin_pb::Wallet>(&[0xff])
+            .unwrap_err());
+    assert_eq!(IroncErrorKind::Protobuf, proto_err.kind());
+    assert!(proto_err.cause().is_some());
+
+    let base64_err: IroncError = FromError::from_error(
+        "not base64!".from_base64().unwrap_err());
+    assert_eq!(IroncErrorKind::Decode, base64_err.kind());
+    assert!(base64_err.cause().is_some());
+
+    assert_eq!(IroncErrorKind::Other, IroncError::new("other").kind());
+    assert!(IroncError::new("other").cause().is_none());
 }
@@ -4,11 +4,16 @@
 #![feature(fs)]
 #![feature(int_uint)]
 #![feature(io)]
+#![feature(libc)]
 #![feature(path)]
 #![feature(std_misc)]
 #![feature(unsafe_destructor)]
+extern crate flate2;
 extern crate getopts;
+extern crate libc;
 extern crate nanomsg;
+#[cfg(feature = "parallel")]
+extern crate num_cpus;
 extern crate protobuf;
 extern crate sodiumoxide;
 extern crate time;
@@ -23,6 +28,8 @@ mod error;
 mod app;
 mod service;
 mod ironcoin_pb;
+mod mnemonic;
+mod payment;
 mod staking;
 mod store;
 mod tx;
@@ -36,7 +43,7 @@ use std::str::FromStr;
 
 use getopts::Options;
 use protobuf::Message;
-use rustc_serialize::base64::{self, FromBase64, ToBase64};
+use rustc_serialize::base64::{self, ToBase64};
 
 use block::GenesisBuilder;
 use blocktree::BlockTreeStore;
@@ -45,7 +52,7 @@ use error::{IroncResult, IroncError};
 use service::{RpcService, IroncService};
 use ironcoin_pb::HashedBlock;
 use store::RocksStore;
-use wallet::{load_proto_from_file, WalletExt, WalletKeypairExt};
+use wallet::{load_proto_from_file, KeyEncoding, WalletExt, WalletKeypairExt};
 
 fn create_genesis_block_from_cmdline(tx_strs: &[String])
                                      -> IroncResult<HashedBlock>
@@ -58,8 +65,7 @@ fn create_genesis_block_from_cmdline(tx_strs: &[String])
                 "A genesis transfer needs to be specified as ADDR:AMOUNT"));
         };
 
-        let maybe_destination =
-            PublicKey::from_slice(&try!(FromBase64::from_base64(transfer_parts[0])));
+        let maybe_destination = transfer_parts[0].parse::<PublicKey>();
         if maybe_destination.is_err() {
             return Err(IroncError::new(&format!(
                 "Could not parse \"{}\" as an address.", transfer_parts[0])));
@@ -107,6 +113,8 @@ fn main() {
                     "NAME");
     opts.optflagopt("", "ls", "List all addresses contained by the wallet.",
                     "PATTERN");
+    opts.optflag("", "hex", "Print keys as hex instead of base64.");
+    opts.optflag("", "info", "Print a summary of the wallet.");
     opts.optopt("g", "", "Set genesis block from file.", "PATH");
     opts.optopt("", "blocktree", "Specify blocktree database.", "PATH");
     opts.optopt("", "new-genesis", "Create a genesis block and write it to file.
@@ -127,6 +135,8 @@ used multiple times to specify genesis transactions.", "ADDR:AMOUNT");
 
     let rpc_endpoint = matches.opt_str("d")
         .unwrap_or(String::from_str(DEFAULT_ENDPOINT));
+    let key_encoding = if matches.opt_present("hex") { KeyEncoding::Hex }
+                       else { KeyEncoding::Base64 };
     let mut wallet = ironcoin_pb::Wallet::new();
     if matches.opt_present("f") {
         let wallet_file = matches.opt_str("f").unwrap();
@@ -134,12 +144,19 @@ used multiple times to specify genesis transactions.", "ADDR:AMOUNT");
         wallet = wallet::load_from_file(&wallet_file).unwrap();
 
         if matches.opt_present("new") {
-            let key = match matches.opt_str("new") {
+            let generated = match matches.opt_str("new") {
                 Some(name) => wallet.generate_new_key(&name),
                 None => {
                     let name = wallet.generate_name();
                     wallet.generate_new_key(&name)
                 }};
+            let key = match generated {
+                Ok(key) => key,
+                Err(err) => {
+                    println!("{}", err);
+                    return;
+                }
+            };
             println!("Created new address: {}",
                      key.get_public_key().to_base64(base64::STANDARD));
             wallet::save_to_file(&wallet_file, &wallet).unwrap()
@@ -148,11 +165,24 @@ used multiple times to specify genesis transactions.", "ADDR:AMOUNT");
             let pattern = matches.opt_str("ls").unwrap_or(String::new());
             if &pattern[..] != "" {
                 let _: Vec<()> = wallet.search_keys(&pattern).iter()
-                    .map(|wkey| { println!("{}", wallet::pretty_format(*wkey)); })
+                    .map(|wkey| {
+                        println!("{}", wallet::pretty_format(*wkey, key_encoding));
+                    })
                     .collect();
             } else {
                 let _: Vec<()> = wallet.get_keypairs().iter().map(
-                    |wkey| println!("{}", wallet::pretty_format(wkey))).collect();
+                    |wkey| println!("{}", wallet::pretty_format(wkey, key_encoding)))
+                    .collect();
+            }
+        }
+        if matches.opt_present("info") {
+            let stats = wallet.stats();
+            println!("keys: {} ({} full, {} watch-only)", stats.key_count,
+                     stats.full_count, stats.watch_only_count);
+            println!("tags: {}", stats.tag_count);
+            if let (Some(earliest), Some(latest)) =
+                (stats.earliest_created_at, stats.latest_created_at) {
+                println!("created: {} to {}", earliest, latest);
             }
         }
         if matches.opt_present("add") {
@@ -164,9 +194,11 @@ used multiple times to specify genesis transactions.", "ADDR:AMOUNT");
                 return;
             }
             let addr_name = addr_parts[0];
-            let public_key = PublicKey::from_slice(
-                &FromBase64::from_base64(addr_parts[1]).unwrap()).unwrap();
-            wallet.add_public_key(&addr_name, &public_key);
+            let public_key = addr_parts[1].parse::<PublicKey>().unwrap();
+            if let Err(err) = wallet.add_public_key(&addr_name, &public_key) {
+                println!("{}", err);
+                return;
+            }
             wallet::save_to_file(&wallet_file, &wallet).unwrap();
         }
     }
@@ -206,16 +238,24 @@ used multiple times to specify genesis transactions.", "ADDR:AMOUNT");
                     "The wallet contains multiple addresses that match \"{}\":",
                     source);
                 let _: Vec<()> = source_keys.iter().map(|wkey| {
-                    println!("{}", wallet::pretty_format(*wkey))}).collect();
+                    println!("{}", wallet::pretty_format(*wkey, key_encoding))
+                }).collect();
                 return;
             }
             let source_sk = source_keys[0].decode_secret_key().unwrap();
             let source_pk = source_keys[0].decode_public_key().unwrap();
             let destination_pk = destination_keys[0].decode_public_key().unwrap();
-            tx_builder
-                .add_transfer(&source_sk, &source_pk, &destination_pk,
-                              amount, op_number)
-                .set_bounty(&source_sk, &source_pk, 1);
+            let added = tx_builder.add_transfer(
+                &source_sk, &source_pk, &destination_pk, amount, op_number);
+            match added {
+                Ok(tx_builder) => {
+                    tx_builder.set_bounty(&source_sk, &source_pk, 1);
+                },
+                Err(err) => {
+                    println!("{}", err);
+                    return;
+                }
+            }
         }
         let transaction = tx_builder.build().unwrap();
         if peers.len() == 0 {
@@ -0,0 +1,124 @@
+use sodiumoxide::crypto::hash::sha256;
+
+use crypto::MASTER_SEED_BYTES;
+use error::{IroncError, IroncErrorKind, IroncResult};
+
+// Mnemonic phrases encode a master seed (see crypto::derive_key) as in BIP39:
+// the seed is followed by the first MASTER_SEED_BYTES / 4 bits of its sha256
+// hash as a checksum, and every 11 bits of the result pick a word from the
+// BIP39 English wordlist. A 32 byte seed gives 24 words.
+
+const WORDLIST: &'static str = include_str!("bip39_english.txt");
+
+const BITS_PER_WORD: usize = 11;
+const CHECKSUM_BITS: usize = MASTER_SEED_BYTES / 4;
+pub const MNEMONIC_WORDS: usize =
+    (MASTER_SEED_BYTES * 8 + CHECKSUM_BITS) / BITS_PER_WORD;
+
+fn words() -> Vec<&'static str> { WORDLIST.lines().collect() }
+
+fn checksum(seed: &[u8]) -> u8 { sha256::hash(seed).0[0] }
+
+fn get_bit(bytes: &[u8], bit: usize) -> bool {
+    bytes[bit / 8] & (0x80 >> (bit % 8)) != 0
+}
+
+pub fn seed_to_mnemonic(seed: &[u8; MASTER_SEED_BYTES]) -> String {
+    let words = words();
+    let mut payload = seed.to_vec();
+    payload.push(checksum(seed));
+
+    let mut phrase = Vec::<&str>::new();
+    for word_index in range(0, MNEMONIC_WORDS) {
+        let mut index = 0usize;
+        for bit in range(0, BITS_PER_WORD) {
+            index <<= 1;
+            if get_bit(&payload, word_index * BITS_PER_WORD + bit) { index |= 1; }
+        }
+        phrase.push(words[index]);
+    }
+    phrase.connect(" ")
+}
+
+pub fn mnemonic_to_seed(phrase: &str) -> IroncResult<[u8; MASTER_SEED_BYTES]> {
+    let words = words();
+    let phrase_words: Vec<&str> = phrase.words().collect();
+    if phrase_words.len() != MNEMONIC_WORDS {
+        return Err(IroncError::with_kind(IroncErrorKind::Decode, &format!(
+            "A mnemonic phrase has {} words, found {}.",
+            MNEMONIC_WORDS, phrase_words.len())));
+    }
+
+    let mut payload = [0u8; MASTER_SEED_BYTES + 1];
+    for (word_index, word) in phrase_words.iter().enumerate() {
+        let index = try!(words.iter().position(|w| w == word).ok_or(
+            IroncError::with_kind(IroncErrorKind::Decode, &format!(
+                "Unknown mnemonic word \"{}\".", word))));
+        for bit in range(0, BITS_PER_WORD) {
+            if index & (1 << (BITS_PER_WORD - 1 - bit)) != 0 {
+                let position = word_index * BITS_PER_WORD + bit;
+                payload[position / 8] |= 0x80 >> (position % 8);
+            }
+        }
+    }
+
+    let mut seed = [0u8; MASTER_SEED_BYTES];
+    for (dst, src) in seed.iter_mut().zip(payload.iter()) { *dst = *src; }
+    if checksum(&seed) != payload[MASTER_SEED_BYTES] {
+        return Err(IroncError::with_kind(IroncErrorKind::Decode,
+                                         "Invalid mnemonic checksum."));
+    }
+    Ok(seed)
+}
+
+/*****  Tests  *****/
+
+#[test]
+fn test_wordlist() {
+    let words = words();
+    assert_eq!(2048, words.len());
+    assert_eq!("abandon", words[0]);
+    assert_eq!("zoo", words[2047]);
+}
+
+#[test]
+fn test_seed_to_mnemonic_vectors() {
+    // Test vectors from BIP39.
+    assert_eq!(
+        "abandon abandon abandon abandon abandon abandon abandon abandon \
+         abandon abandon abandon abandon abandon abandon abandon abandon \
+         abandon abandon abandon abandon abandon abandon abandon art",
+        seed_to_mnemonic(&[0x00; MASTER_SEED_BYTES]));
+    assert_eq!(
+        "legal winner thank year wave sausage worth useful legal winner \
+         thank year wave sausage worth useful legal winner thank year wave \
+         sausage worth title",
+        seed_to_mnemonic(&[0x7f; MASTER_SEED_BYTES]));
+    assert_eq!(
+        "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo \
+         zoo zoo zoo zoo zoo zoo vote",
+        seed_to_mnemonic(&[0xff; MASTER_SEED_BYTES]));
+}
+
+#[test]
+fn test_mnemonic_round_trip() {
+    use crypto::gen_master_seed;
+    let seed = gen_master_seed();
+    let phrase = seed_to_mnemonic(&seed);
+    assert_eq!(MNEMONIC_WORDS, phrase.words().count());
+    assert_eq!(seed, mnemonic_to_seed(&phrase).unwrap());
+}
+
+#[test]
+fn test_mnemonic_to_seed_errors() {
+    let valid = seed_to_mnemonic(&[0x7f; MASTER_SEED_BYTES]);
+    assert!(mnemonic_to_seed(&valid).is_ok());
+
+    assert_eq!(Err(IroncError::with_kind(IroncErrorKind::Decode,
+                                         "Invalid mnemonic checksum.")),
+               mnemonic_to_seed(&valid.replace("title", "thank")));
+    assert_eq!(Err(IroncError::with_kind(IroncErrorKind::Decode,
+                                         "Unknown mnemonic word \"ironcoin\".")),
+               mnemonic_to_seed(&valid.replace("title", "ironcoin")));
+    assert!(mnemonic_to_seed("legal winner thank").is_err());
+}
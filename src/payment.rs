@@ -0,0 +1,128 @@
+use rustc_serialize::hex::FromHex;
+
+use crypto::PublicKey;
+use error::{IroncError, IroncErrorKind, IroncResult};
+
+// Payment URIs, e.g. for click-to-pay links and QR codes, look like
+//   ironcoin:<address>?amount=<tokens>&memo=<text>
+// The amount is required and the memo is optional and percent-encoded. See
+// TransactionBuilder::add_payment_request to pay one.
+
+pub const PAYMENT_URI_SCHEME: &'static str = "ironcoin:";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PaymentRequest {
+    pub destination: PublicKey,
+    pub tokens: u64,
+    pub memo: Option<String>
+}
+
+fn decode_error(description: &str) -> IroncError {
+    IroncError::with_kind(IroncErrorKind::Decode, description)
+}
+
+fn percent_decode(encoded: &str) -> IroncResult<String> {
+    let bytes = encoded.as_bytes();
+    let mut decoded = vec![];
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'%' => {
+                let is_escape = index + 2 < bytes.len() &&
+                    (bytes[index + 1] as char).is_digit(16) &&
+                    (bytes[index + 2] as char).is_digit(16);
+                if !is_escape {
+                    return Err(decode_error(&format!(
+                        "Invalid percent escape in \"{}\".", encoded)));
+                }
+                decoded.push_all(&try!(encoded[index + 1..index + 3].from_hex()));
+                index += 3;
+                continue;
+            },
+            b'+' => decoded.push(b' '),
+            byte => decoded.push(byte)
+        }
+        index += 1;
+    }
+    String::from_utf8(decoded).map_err(|_| decode_error(&format!(
+        "\"{}\" doesn't decode to UTF-8 text.", encoded)))
+}
+
+pub fn parse_payment_uri(uri: &str) -> IroncResult<PaymentRequest> {
+    if !uri.starts_with(PAYMENT_URI_SCHEME) {
+        return Err(decode_error(&format!(
+            "Payment URI \"{}\" doesn't start with \"{}\".",
+            uri, PAYMENT_URI_SCHEME)));
+    }
+    let rest = &uri[PAYMENT_URI_SCHEME.len()..];
+    let (address, query) = match rest.find('?') {
+        Some(index) => (&rest[..index], &rest[index + 1..]),
+        None => (rest, "")
+    };
+    let destination = try!(PublicKey::from_address(address).map_err(
+        |err| decode_error(&format!("Invalid address in payment URI: {}", err))));
+
+    let mut tokens = None;
+    let mut memo = None;
+    for param in query.split('&').filter(|param| !param.is_empty()) {
+        let (key, value) = match param.find('=') {
+            Some(index) => (&param[..index], &param[index + 1..]),
+            None => return Err(decode_error(&format!(
+                "Payment URI parameter \"{}\" has no value.", param)))
+        };
+        match key {
+            "amount" if tokens.is_none() => {
+                tokens = Some(try!(value.parse::<u64>().map_err(
+                    |_| decode_error(&format!(
+                        "Invalid amount \"{}\" in payment URI.", value)))));
+            },
+            "memo" if memo.is_none() => memo = Some(try!(percent_decode(value))),
+            _ => return Err(decode_error(&format!(
+                "Unexpected or repeated parameter \"{}\" in payment URI.", key)))
+        }
+    }
+    let tokens = try!(tokens.ok_or(decode_error("Payment URI has no amount.")));
+    Ok(PaymentRequest { destination: destination, tokens: tokens, memo: memo })
+}
+
+/*****  Tests  *****/
+
+use crypto::gen_keypair;
+
+#[test]
+fn test_parse_payment_uri() {
+    let (pk, _) = gen_keypair();
+    let address = pk.to_address();
+    assert_eq!(PaymentRequest { destination: pk.clone(), tokens: 25, memo: None },
+               parse_payment_uri(&format!("ironcoin:{}?amount=25", address))
+               .unwrap());
+    assert_eq!(PaymentRequest { destination: pk.clone(), tokens: 7,
+                                memo: Some(String::from_str("invoice #42 ok")) },
+               parse_payment_uri(&format!(
+                   "ironcoin:{}?memo=invoice+%2342%20ok&amount=7", address))
+               .unwrap());
+}
+
+#[test]
+fn test_parse_payment_uri_errors() {
+    let (pk, _) = gen_keypair();
+    let address = pk.to_address();
+    let parse_error = |uri: String| parse_payment_uri(&uri).unwrap_err();
+
+    assert_eq!(decode_error(&format!(
+        "Payment URI \"bitcoin:{}\" doesn't start with \"ironcoin:\".", address)),
+        parse_error(format!("bitcoin:{}", address)));
+    assert_eq!(decode_error("Payment URI has no amount."),
+               parse_error(format!("ironcoin:{}", address)));
+    assert_eq!(decode_error("Invalid amount \"ten\" in payment URI."),
+               parse_error(format!("ironcoin:{}?amount=ten", address)));
+    assert_eq!(decode_error(
+        "Unexpected or repeated parameter \"amount\" in payment URI."),
+        parse_error(format!("ironcoin:{}?amount=1&amount=2", address)));
+    assert_eq!(decode_error("Payment URI parameter \"memo\" has no value."),
+               parse_error(format!("ironcoin:{}?amount=1&memo", address)));
+    assert_eq!(decode_error("Invalid percent escape in \"100%\"."),
+               parse_error(format!("ironcoin:{}?amount=1&memo=100%", address)));
+    assert_eq!(IroncErrorKind::Decode,
+               parse_error(format!("ironcoin:{}x?amount=1", address)).kind());
+}
@@ -20,7 +20,7 @@ use ironcoin_pb::{self, HashedBlock, GetBlocksRequest,
                   RpcRequest_Method, SignedRpcRequest, Transaction};
 use staking::BlockTemplate;
 use store::RocksStore;
-use tx::TransactionExt;
+use tx::{DEFAULT_CHAIN_ID, TransactionExt};
 
 pub fn wrap_get_blocks_request(request: GetBlocksRequest) -> RpcRequest {
     let mut wrapped_request = RpcRequest::new();
@@ -105,8 +105,10 @@ impl IroncService {
 
     fn prune_invalid_transactions(&mut self) {
         let mut snapshot = self.blocktree.snapshot();
+        let now = now_utc().to_timespec().sec as u64;
         let pending = self.pending_transactions.drain().filter(
-            |tx| snapshot.apply_transaction(&tx).is_ok()).collect();
+            |tx| !tx.is_expired(now) && snapshot.apply_transaction(&tx).is_ok())
+            .collect();
         self.pending_transactions = pending;
     }
 
@@ -163,9 +165,13 @@ impl StakerService for IroncService {
             block.set_height(block_height);
             block.set_target_hash(template.proof_hash.0.to_vec());
 
+            // Transactions expire against the block's timestamp, which may
+            // differ slightly from the time used by the pruning.
             self.prune_invalid_transactions();
-            block.set_transactions(
-                FromIterator::from_iter(self.pending_transactions.drain()));
+            let timestamp = template.timestamp as u64;
+            block.set_transactions(FromIterator::from_iter(
+                self.pending_transactions.drain()
+                    .filter(|tx| !tx.is_expired(timestamp))));
         }
         staked_block.mut_signed_block().sign(&template.staker_sk);
         let staked_hash = staked_block.compute_hash();
@@ -298,7 +304,14 @@ impl RpcService for IroncService {
             return Ok(response);
         }
         let transaction = request.take_transaction();
-        let checked = transaction.verify_signatures();
+        if transaction.is_coinbase() {
+            response.set_status(ResponseStatus::INVALID_REQUEST);
+            response.set_description(String::from_str(
+                "Coinbase transactions are only valid in the genesis block."));
+            return Ok(response);
+        }
+        let checked = transaction.verify_signatures_at(
+            DEFAULT_CHAIN_ID, now_utc().to_timespec().sec as u64);
         if checked.is_err() {
             response.set_status(ResponseStatus::INVALID_REQUEST);
             response.set_description(
@@ -5,7 +5,7 @@ use time::now_utc;
 use crypto::{hash, HashDigest, PublicKey, SecretKey};
 use error::IroncResult;
 use ironcoin_pb::Wallet;
-use wallet::{self, WalletKeypairExt};
+use wallet::{self, KeyEncoding, WalletKeypairExt};
 
 // #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct BlockTemplate {
@@ -74,7 +74,7 @@ impl Staker {
                 if *proof_hash < self.target_hash {
                     let keypair = &self.staking_keys.get_keypairs()[wallet_index];
                     println!("Hoo-yeah! Successfuly staked a block with address {}",
-                             wallet::pretty_format(&keypair));
+                             wallet::pretty_format(&keypair, KeyEncoding::Base64));
                     return Ok(Some(BlockTemplate {
                         proof_hash: proof_hash.clone(),
                         previous_block: self.head_block.clone(),
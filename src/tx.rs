@@ -1,96 +1,2244 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::i64;
+use std::u64;
 
-use protobuf::Message;
+use protobuf::{self, Message, ProtobufEnum};
+use rustc_serialize::base64::{self, FromBase64, ToBase64};
+use rustc_serialize::json;
 
-use crypto::{PublicKey, SecretKey, Signature, sign, verify_signature};
-use ironcoin_pb::{Commitment, DetachedSignature, Transaction, Transfer};
-use error::{IroncError, IroncResult};
+use crypto::{PublicKey, SecretKey, Signature, derive_public_key, hash,
+             multisig_account, sign, verify_signature};
+use ironcoin_pb::{Commitment, Commitment_Type, DetachedSignature, Transaction,
+                  Transfer, Wallet};
+use error::{IroncError, IroncErrorKind, IroncResult};
+use payment::PaymentRequest;
+use wallet::{WalletExt, WalletKeypairExt};
+
+// Chain id of the main network; also the id of commits without a chain_id.
+pub const DEFAULT_CHAIN_ID: u32 = 0;
+
+// Signatures are over SIGNING_VERSION || SIGNING_DOMAIN || serialized commit
+// rather than over the bare commit, so the signed payload can't be mistaken
+// for another message and can change format under a new version.
+pub const SIGNING_VERSION: u8 = 1;
+pub const SIGNING_DOMAIN: &'static [u8] = b"ironcoin-commit-v1";
+
+pub const MAX_MEMO_BYTES: usize = 256;
+
+// Identifies a transaction by its commit (see TransactionExt::txid), so two
+// copies with their signatures in a different order are equal and hash the
+// same, e.g. when deduplicating transactions in a HashSet.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct TxId(pub [u8; 32]);
+
+impl TxId {
+    pub fn of(tx: &Transaction) -> IroncResult<TxId> { Ok(TxId(try!(tx.txid()))) }
+}
+
+impl fmt::Display for TxId {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{}", self.0.to_base64(base64::STANDARD))
+    }
+}
+
+pub trait CommitmentExt {
+    fn merkle_proof(&self, index: usize) -> IroncResult<Vec<[u8; 32]>>;
+    fn signing_bytes(&self) -> IroncResult<Vec<u8>>;
+    fn transfers_merkle_root(&self) -> IroncResult<[u8; 32]>;
+}
+
+// Merkle tree over the transfers of a commit:
+//   leaf     = hash(0x00 || serialized Transfer)
+//   node     = hash(0x01 || left || right)
+//   root     = hash(0x02 || number of transfers as a big endian u64 || top node)
+//   no transfers: the root is hash of the empty string.
+// A level with an odd number of nodes pairs its last node with itself. A proof
+// is the list of siblings from the leaf up to the top node, leaving out those
+// self pairings. The number of transfers is part of the root, so it fixes the
+// shape of the tree and a proof only verifies at the index it was made for.
+
+fn merkle_leaf(transfer: &Transfer) -> IroncResult<[u8; 32]> {
+    let mut leaf_bytes = vec![0u8];
+    leaf_bytes.push_all(&try!(transfer.write_to_bytes()));
+    Ok(hash(&leaf_bytes).0)
+}
+
+fn merkle_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut node_bytes = vec![1u8];
+    node_bytes.push_all(left);
+    node_bytes.push_all(right);
+    hash(&node_bytes).0
+}
+
+fn merkle_parent_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level.chunks(2).map(|pair| {
+        merkle_node(&pair[0], if pair.len() == 2 { &pair[1] } else { &pair[0] })
+    }).collect()
+}
+
+fn merkle_root(transfer_count: usize, top: &[u8; 32]) -> [u8; 32] {
+    let mut root_bytes = vec![2u8];
+    let count = transfer_count as u64;
+    for shift in range(0, 8).rev() {
+        root_bytes.push((count >> (8 * shift)) as u8);
+    }
+    root_bytes.push_all(top);
+    hash(&root_bytes).0
+}
+
+fn merkle_leaves(commit: &Commitment) -> IroncResult<Vec<[u8; 32]>> {
+    let mut leaves = vec![];
+    for transfer in commit.get_transfers().iter() {
+        leaves.push(try!(merkle_leaf(transfer)));
+    }
+    Ok(leaves)
+}
+
+// `transfer_count` is the number of transfers in the commit; a wrong count
+// doesn't verify, as it's part of the root.
+pub fn verify_merkle_proof(root: &[u8; 32], transfer: &Transfer, index: usize,
+                           transfer_count: usize, proof: &[[u8; 32]])
+                           -> IroncResult<bool> {
+    if index >= transfer_count { return Ok(false); }
+    let mut node = try!(merkle_leaf(transfer));
+    let mut position = index;
+    let mut level_len = transfer_count;
+    let mut siblings = proof.iter();
+    while level_len > 1 {
+        node = if position % 2 == 1 || position + 1 < level_len {
+            let sibling = match siblings.next() {
+                Some(sibling) => sibling,
+                None => return Ok(false)
+            };
+            if position % 2 == 1 { merkle_node(sibling, &node) }
+            else { merkle_node(&node, sibling) }
+        } else {
+            merkle_node(&node, &node)
+        };
+        position /= 2;
+        level_len = (level_len + 1) / 2;
+    }
+    Ok(siblings.next().is_none() && merkle_root(transfer_count, &node) == *root)
+}
+
+impl CommitmentExt for Commitment {
+    fn merkle_proof(&self, index: usize) -> IroncResult<Vec<[u8; 32]>> {
+        let mut level = try!(merkle_leaves(self));
+        if index >= level.len() {
+            return Err(IroncError::new(&format!(
+                "No transfer at index {}, the commit has {} transfers.",
+                index, level.len())));
+        }
+        let mut proof = vec![];
+        let mut position = index;
+        while level.len() > 1 {
+            if position % 2 == 1 {
+                proof.push(level[position - 1].clone());
+            } else if position + 1 < level.len() {
+                proof.push(level[position + 1].clone());
+            }
+            level = merkle_parent_level(&level);
+            position /= 2;
+        }
+        Ok(proof)
+    }
+
+    // The exact payload of every signature on a transaction with this commit,
+    // for external signers such as hardware wallets: signing it with
+    // crypto::sign gives a signature for attach_signature. TransactionBuilder
+    // and verify_signatures both go through this (via commit_bytes).
+    fn signing_bytes(&self) -> IroncResult<Vec<u8>> {
+        let mut signing_bytes = vec![SIGNING_VERSION];
+        signing_bytes.push_all(SIGNING_DOMAIN);
+        signing_bytes.push_all(&try!(self.write_to_bytes()));
+        Ok(signing_bytes)
+    }
+
+    fn transfers_merkle_root(&self) -> IroncResult<[u8; 32]> {
+        let mut level = try!(merkle_leaves(self));
+        if level.is_empty() { return Ok(hash(&[]).0); }
+        let transfer_count = level.len();
+        while level.len() > 1 { level = merkle_parent_level(&level); }
+        Ok(merkle_root(transfer_count, &level[0]))
+    }
+}
 
 pub trait TransactionExt {
-    fn verify_signatures(&self) -> IroncResult<()>;
+    fn attach_signature(&mut self, public_key: &PublicKey, signature: &Signature);
+    fn commit_bytes(&self) -> IroncResult<Vec<u8>>;
+    fn destination_pubkeys(&self) -> IroncResult<Vec<PublicKey>>;
+    fn fee(&self) -> u64;
+    fn into_unsigned_commit(self) -> Commitment;
+    fn is_coinbase(&self) -> bool;
+    fn is_expired(&self, now: u64) -> bool;
+    fn is_fully_signed(&self) -> bool;
+    fn merge_signatures(&mut self, other: &Transaction) -> IroncResult<()>;
+    fn net_delta(&self) -> IroncResult<HashMap<PublicKey, i64>>;
+    fn resign(&mut self, signers: &[(PublicKey, SecretKey)]) -> IroncResult<()>;
+    fn serialized_size(&self) -> IroncResult<usize>;
+    fn source_pubkeys(&self) -> IroncResult<Vec<PublicKey>>;
+    fn txid(&self) -> IroncResult<[u8; 32]>;
+    fn unsigned_sources(&self) -> IroncResult<Vec<PublicKey>>;
+    fn validate_structure(&self) -> IroncResult<()>;
+    fn verify_all(&self) -> Vec<VerificationIssue>;
+    fn verify_signatures(&self, chain_id: u32) -> IroncResult<()>;
+    fn verify_signatures_at(&self, chain_id: u32, now: u64) -> IroncResult<()>;
+    fn verify_signatures_with_policy(
+        &self, chain_id: u32, allowed: &HashSet<PublicKey>) -> IroncResult<()>;
 }
 
+// Offline signing works as follows:
+//   1. On the online machine, add the transfers with add_unsigned_transfer
+//      (and set_unsigned_bounty) and call TransactionBuilder::build_unsigned.
+//   2. Move commit_bytes() of the unsigned transaction to the offline machine
+//      and sign them there with crypto::sign, once per source (and for the
+//      bounty payer if there is a bounty).
+//   3. Back on the online machine, call attach_signature for each signature
+//      and check the result with verify_signatures before publishing it.
+// The secret keys never need to leave the offline machine.
 impl TransactionExt for Transaction {
-    fn verify_signatures(&self) -> IroncResult<()> {
-        let commit_bytes = &try!(self.get_commit().write_to_bytes());
-        let mut sign_map = HashMap::<&[u8], &[u8]>::new();
-        for sign in self.get_signatures().iter() {
-            sign_map.insert(sign.get_public_key(), sign.get_payload());
+    fn attach_signature(&mut self, public_key: &PublicKey, signature: &Signature) {
+        let mut sign = DetachedSignature::new();
+        sign.set_public_key(public_key.0.to_vec());
+        sign.set_payload(signature.0.to_vec());
+        self.mut_signatures().push(sign);
+    }
+
+    // The bytes signed by each signature, see CommitmentExt::signing_bytes.
+    fn commit_bytes(&self) -> IroncResult<Vec<u8>> {
+        self.get_commit().signing_bytes()
+    }
+
+    // Destinations in the order they first appear, without duplicates.
+    fn destination_pubkeys(&self) -> IroncResult<Vec<PublicKey>> {
+        let mut destinations = Vec::<PublicKey>::new();
+        for transfer in self.get_commit().get_transfers().iter() {
+            let destination = try!(PublicKey::from_slice(
+                transfer.get_destination_pk()));
+            if !destinations.contains(&destination) {
+                destinations.push(destination);
+            }
+        }
+        Ok(destinations)
+    }
+
+    fn fee(&self) -> u64 { self.get_commit().get_bounty() }
+
+    // Drops the signatures, e.g. to edit the commit with
+    // TransactionBuilder::from_commit and sign it again.
+    fn into_unsigned_commit(mut self) -> Commitment { self.take_commit() }
+
+    fn is_coinbase(&self) -> bool {
+        self.get_commit().get_tx_type() == Commitment_Type::COINBASE
+    }
+
+    // See Commitment.valid_until; `now` is a unix timestamp in seconds.
+    fn is_expired(&self, now: u64) -> bool {
+        let valid_until = self.get_commit().get_valid_until();
+        valid_until != 0 && now > valid_until
+    }
+
+    // False if the commit can't be serialized.
+    fn is_fully_signed(&self) -> bool {
+        self.unsigned_sources().map(|unsigned| unsigned.is_empty())
+            .unwrap_or(false) &&
+            self.verify_signatures(self.get_commit().get_chain_id()).is_ok()
+    }
+
+    // Adds the signatures of `other`, a copy of this transaction signed by
+    // other parties. Public keys that already have a signature are skipped.
+    fn merge_signatures(&mut self, other: &Transaction) -> IroncResult<()> {
+        let (txid, other_txid) = (try!(self.txid()), try!(other.txid()));
+        if txid != other_txid {
+            return Err(IroncError::new(&format!(
+                "Can't merge signatures for different commits: {} != {}",
+                TxId(txid), TxId(other_txid))));
+        }
+        for sign in other.get_signatures().iter() {
+            let already_signed = self.get_signatures().iter().any(
+                |own| own.get_public_key() == sign.get_public_key());
+            if !already_signed { self.mut_signatures().push(sign.clone()); }
+        }
+        Ok(())
+    }
+
+    // The balance change of every account the transaction touches. The bounty
+    // is charged to its payer; it goes to the staker of the block, which isn't
+    // part of the transaction.
+    fn net_delta(&self) -> IroncResult<HashMap<PublicKey, i64>> {
+        let commit = self.get_commit();
+        // (account, tokens, whether the account receives the tokens)
+        let mut changes = Vec::<(&[u8], u64, bool)>::new();
+        for transfer in commit.get_transfers().iter() {
+            let tokens = transfer.get_tokens();
+            changes.push((transfer.get_source_pk(), tokens, false));
+            changes.push((transfer.get_destination_pk(), tokens, true));
         }
+        if commit.get_bounty() > 0 {
+            changes.push((commit.get_bounty_pk(), commit.get_bounty(), false));
+        }
+        let mut deltas = HashMap::<PublicKey, i64>::new();
+        for &(pk_bytes, tokens, incoming) in changes.iter() {
+            let public_key = try!(PublicKey::from_slice(pk_bytes));
+            if tokens > i64::MAX as u64 { return Err(IroncError::token_overflow()); }
+            let delta = *deltas.get(&public_key).unwrap_or(&0);
+            let new_delta = if incoming { delta.checked_add(tokens as i64) }
+                            else { delta.checked_sub(tokens as i64) };
+            deltas.insert(public_key,
+                          try!(new_delta.ok_or(IroncError::token_overflow())));
+        }
+        Ok(deltas)
+    }
+
+    // Replaces the signatures with new ones over the current commit, e.g.
+    // after bumping the fee. Fails, leaving the transaction unchanged, if
+    // `signers` don't cover every source and the bounty payer.
+    fn resign(&mut self, signers: &[(PublicKey, SecretKey)]) -> IroncResult<()> {
+        let mut tx_builder =
+            TransactionBuilder::from_commit(self.get_commit().clone());
+        for &(ref public_key, ref secret_key) in signers.iter() {
+            if derive_public_key(secret_key) != *public_key {
+                return Err(IroncError::crypto(&format!(
+                    "The secret key given for {} doesn't derive it.",
+                    public_key.to_address())));
+            }
+            tx_builder.add_secret_key(secret_key);
+        }
+        *self = try!(tx_builder.build());
+        Ok(())
+    }
+
+    fn serialized_size(&self) -> IroncResult<usize> {
+        Ok(try!(self.write_to_bytes()).len())
+    }
+
+    // Sources in the order they first appear, without duplicates.
+    fn source_pubkeys(&self) -> IroncResult<Vec<PublicKey>> {
+        let mut sources = Vec::<PublicKey>::new();
+        for transfer in self.get_commit().get_transfers().iter() {
+            let source = try!(PublicKey::from_slice(transfer.get_source_pk()));
+            if !sources.contains(&source) { sources.push(source); }
+        }
+        Ok(sources)
+    }
+
+    // Only the commit is hashed, so the id doesn't depend on the signatures
+    // or the order they are attached in.
+    fn txid(&self) -> IroncResult<[u8; 32]> {
+        Ok(hash(&try!(self.get_commit().write_to_bytes())).0)
+    }
+
+    // The public keys that still need to sign: sources and the bounty payer
+    // without a valid signature, and for multisig transfers short of their
+    // threshold, the signers that haven't signed yet.
+    fn unsigned_sources(&self) -> IroncResult<Vec<PublicKey>> {
+        let commit_bytes = &try!(self.commit_bytes());
+        let sign_map = signature_map(self);
+        let mut unsigned = Vec::<PublicKey>::new();
         for transfer in self.get_commit().get_transfers().iter() {
+            if transfer.get_multisig_pks().len() == 0 {
+                let source_pk = transfer.get_source_pk();
+                if !has_valid_signature(&sign_map, source_pk, commit_bytes) {
+                    push_unique_key(&mut unsigned, source_pk);
+                }
+            } else if verify_multisig_transfer(
+                transfer, &sign_map, commit_bytes).is_err() {
+                for signer in transfer.get_multisig_pks().iter() {
+                    if !has_valid_signature(&sign_map, signer, commit_bytes) {
+                        push_unique_key(&mut unsigned, signer);
+                    }
+                }
+            }
+        }
+        let bounty_pk = self.get_commit().get_bounty_pk();
+        if self.get_commit().get_bounty() > 0 &&
+            !has_valid_signature(&sign_map, bounty_pk, commit_bytes) {
+            push_unique_key(&mut unsigned, bounty_pk);
+        }
+        Ok(unsigned)
+    }
+
+    // Checks that all the public keys in the commit are well formed. Only
+    // sources are checked by verifying their signatures, so without this a
+    // destination could have any length. Coinbase transfers have no source.
+    fn validate_structure(&self) -> IroncResult<()> {
+        match structure_errors(self).into_iter().next() {
+            Some(err) => Err(err),
+            None => Ok(())
+        }
+    }
+
+    // Runs the checks of verify_signatures (except for the chain id) without
+    // stopping at the first problem, for diagnosing malformed transactions.
+    // No issues means the transaction is valid.
+    fn verify_all(&self) -> Vec<VerificationIssue> {
+        let mut issues: Vec<VerificationIssue> = structure_errors(self)
+            .into_iter().map(|err| VerificationIssue::Structure(err)).collect();
+        if self.is_coinbase() {
+            if let Err(err) = check_coinbase(self) {
+                issues.push(VerificationIssue::Structure(err));
+            }
+            return issues;
+        }
+        let commit_bytes = &match self.commit_bytes() {
+            Ok(commit_bytes) => commit_bytes,
+            Err(err) => {
+                issues.push(VerificationIssue::Structure(err));
+                return issues;
+            }
+        };
+        let sign_map = signature_map(self);
+        for (index, transfer) in self.get_commit().get_transfers().iter()
+            .enumerate()
+        {
+            let source_pk = transfer.get_source_pk();
+            let verified = if transfer.get_multisig_pks().len() > 0 {
+                verify_multisig_transfer(transfer, &sign_map, commit_bytes)
+                    .map_err(|err| multisig_transfer_error(index, transfer, err))
+            } else {
+                match sign_map.get(source_pk) {
+                    Some(sign_bytes) => verify_transfer_signature(
+                        &(index, source_pk, *sign_bytes), commit_bytes),
+                    None => Err(missing_signature_error(index, source_pk))
+                }
+            };
+            if let Err(err) = verified {
+                issues.push(VerificationIssue::Transfer(index, err));
+            }
+        }
+        if let Err(err) = verify_bounty_signature(self, &sign_map, commit_bytes) {
+            issues.push(VerificationIssue::Bounty(err));
+        }
+        for sign in self.get_signatures().iter() {
+            if !is_referenced_signer(self.get_commit(), sign.get_public_key()) {
+                issues.push(VerificationIssue::OrphanSignature(
+                    orphan_signature_error(sign)));
+            }
+        }
+        issues
+    }
+
+    fn verify_signatures(&self, chain_id: u32) -> IroncResult<()> {
+        if self.get_commit().get_chain_id() != chain_id {
+            return Err(IroncError::new(&format!(
+                "Transaction is for chain {}, expected chain {}.",
+                self.get_commit().get_chain_id(), chain_id)));
+        }
+        try!(self.validate_structure());
+        if self.is_coinbase() { return check_coinbase(self); }
+        let commit_bytes = &try!(self.commit_bytes());
+        let sign_map = signature_map(self);
+        let mut checks = Vec::<(usize, &[u8], &[u8])>::new();
+        for (index, transfer) in self.get_commit().get_transfers().iter()
+            .enumerate()
+        {
+            if transfer.get_multisig_pks().len() > 0 {
+                let verified =
+                    verify_multisig_transfer(transfer, &sign_map, commit_bytes);
+                if let Err(err) = verified {
+                    return Err(multisig_transfer_error(index, transfer, err));
+                }
+                continue;
+            }
             match sign_map.get(transfer.get_source_pk()) {
-                Some(sign_bytes) => {
-                    let public_key =
-                        try!(PublicKey::from_slice(transfer.get_source_pk()));
-                    let signature = try!(Signature::from_slice(sign_bytes));
-                    try!(verify_signature(&public_key, commit_bytes, &signature));
-                },
-                None => return Err(IroncError::new("Missing key."))
+                Some(sign_bytes) =>
+                    checks.push((index, transfer.get_source_pk(), *sign_bytes)),
+                None => return Err(
+                    missing_signature_error(index, transfer.get_source_pk()))
+            }
+        }
+        try!(verify_transfer_signatures(&checks, commit_bytes));
+        try!(verify_bounty_signature(self, &sign_map, commit_bytes));
+        for sign in self.get_signatures().iter() {
+            if !is_referenced_signer(self.get_commit(), sign.get_public_key()) {
+                return Err(orphan_signature_error(sign));
             }
         }
         Ok(())
     }
+
+    // Like verify_signatures, but also rejects transactions that expired
+    // before `now` (e.g. the current time, or a block's timestamp).
+    fn verify_signatures_at(&self, chain_id: u32, now: u64) -> IroncResult<()> {
+        try!(self.verify_signatures(chain_id));
+        if self.is_expired(now) {
+            return Err(IroncError::new(&format!(
+                "Transaction expired at {} (now {}).",
+                self.get_commit().get_valid_until(), now)));
+        }
+        Ok(())
+    }
+
+    // Permissioned (consortium or private) chains only accept transactions
+    // signed by known parties. This runs the checks of verify_signatures and
+    // then rejects signers that aren't in `allowed`, even with valid
+    // signatures.
+    fn verify_signatures_with_policy(
+        &self, chain_id: u32, allowed: &HashSet<PublicKey>) -> IroncResult<()> {
+        try!(self.verify_signatures(chain_id));
+        for sign in self.get_signatures().iter() {
+            let signer = try!(PublicKey::from_slice(sign.get_public_key()));
+            if !allowed.contains(&signer) {
+                return Err(IroncError::new(&format!(
+                    "Signer {} isn't allowed to sign transactions.",
+                    signer.to_address())));
+            }
+        }
+        Ok(())
+    }
+}
+
+// A problem found by TransactionExt::verify_all. Transfer issues carry the
+// index of the transfer.
+#[derive(Debug, PartialEq)]
+pub enum VerificationIssue {
+    Structure(IroncError),
+    Transfer(usize, IroncError),
+    Bounty(IroncError),
+    OrphanSignature(IroncError)
+}
+
+fn check_public_key(pk_bytes: &[u8], context: &str) -> IroncResult<()> {
+    match PublicKey::from_slice(pk_bytes) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(IroncError::new(&format!("{}: {}", context, err)))
+    }
+}
+
+fn structure_errors(tx: &Transaction) -> Vec<IroncError> {
+    let commit = tx.get_commit();
+    let mut checks = vec![];
+    for (index, transfer) in commit.get_transfers().iter().enumerate() {
+        if !tx.is_coinbase() {
+            checks.push(check_public_key(transfer.get_source_pk(), &format!(
+                "Transfer {} has an invalid source public key", index)));
+        }
+        checks.push(check_public_key(transfer.get_destination_pk(), &format!(
+            "Transfer {} has an invalid destination public key", index)));
+        for signer in transfer.get_multisig_pks().iter() {
+            checks.push(check_public_key(signer, &format!(
+                "Transfer {} has an invalid multisig public key", index)));
+        }
+    }
+    if commit.get_bounty() > 0 {
+        checks.push(check_public_key(commit.get_bounty_pk(),
+                                     "The bounty has an invalid public key"));
+    }
+    checks.into_iter().filter_map(|check| check.err()).collect()
+}
+
+fn missing_signature_error(index: usize, source_pk: &[u8]) -> IroncError {
+    IroncError::missing_key(&format!(
+        "Transfer {} ({}) is missing a signature.", index,
+        source_address(source_pk)))
+}
+
+fn multisig_transfer_error(index: usize, transfer: &Transfer, err: IroncError)
+                           -> IroncError {
+    IroncError::with_kind(err.kind(), &format!(
+        "Transfer {} ({}): {}", index, source_address(transfer.get_source_pk()),
+        err))
+}
+
+fn orphan_signature_error(sign: &DetachedSignature) -> IroncError {
+    IroncError::new(&format!(
+        "Signature from {} doesn't belong to any transfer or the bounty.",
+        sign.get_public_key().to_base64(base64::STANDARD)))
+}
+
+fn verify_bounty_signature(tx: &Transaction, sign_map: &HashMap<&[u8], &[u8]>,
+                           commit_bytes: &[u8]) -> IroncResult<()> {
+    if tx.get_commit().get_bounty() == 0 { return Ok(()); }
+    let bounty_pk = tx.get_commit().get_bounty_pk();
+    match sign_map.get(bounty_pk) {
+        Some(sign_bytes) =>
+            verify_detached_signature(bounty_pk, sign_bytes, commit_bytes),
+        None => Err(IroncError::missing_key(
+            "Missing signature for the bounty public key."))
+    }
+}
+
+// A coinbase has nothing to sign, so this only checks its shape. Anyone can
+// create one: it's up to the caller to only accept them in the genesis block
+// (see block::HashedBlockExt::verify), since a coinbase can't be told apart
+// from a forged one.
+fn check_coinbase(tx: &Transaction) -> IroncResult<()> {
+    let commit = tx.get_commit();
+    let well_formed = commit.get_transfers().len() == 1 &&
+        commit.get_transfers()[0].get_source_pk().is_empty() &&
+        commit.get_transfers()[0].get_multisig_pks().is_empty() &&
+        commit.get_bounty() == 0 && !commit.has_bounty_pk() &&
+        tx.get_signatures().is_empty();
+    if well_formed { Ok(()) }
+    else {
+        Err(IroncError::new("A coinbase transaction must have a single \
+                             transfer without a source and no signatures."))
+    }
+}
+
+// Maps the public key of each signature to its payload.
+fn signature_map(tx: &Transaction) -> HashMap<&[u8], &[u8]> {
+    let mut sign_map = HashMap::<&[u8], &[u8]>::new();
+    for sign in tx.get_signatures().iter() {
+        sign_map.insert(sign.get_public_key(), sign.get_payload());
+    }
+    sign_map
+}
+
+fn has_valid_signature(sign_map: &HashMap<&[u8], &[u8]>, public_key: &[u8],
+                       commit_bytes: &[u8]) -> bool {
+    match sign_map.get(public_key) {
+        Some(sign_bytes) => verify_detached_signature(
+            public_key, sign_bytes, commit_bytes).is_ok(),
+        None => false
+    }
+}
+
+fn push_unique_key(keys: &mut Vec<PublicKey>, public_key: &[u8]) {
+    if let Ok(public_key) = PublicKey::from_slice(public_key) {
+        if !keys.contains(&public_key) { keys.push(public_key); }
+    }
+}
+
+// op_index is the sequence number of a transfer within its source account
+// (see balance::LedgerSnapshot::add_transfer), so different sources may share
+// an op_index. Transfers from the same source must have consecutive op_index
+// values in the order they appear in the commit, which also rules out
+// duplicates.
+fn check_op_indices(commit: &Commitment) -> IroncResult<()> {
+    let mut last_op_index = HashMap::<&[u8], u32>::new();
+    for transfer in commit.get_transfers().iter() {
+        let source = transfer.get_source_pk();
+        if let Some(previous) = last_op_index.get(source) {
+            if transfer.get_op_index() != *previous + 1 {
+                return Err(IroncError::new(&format!(
+                    "Transfers from {} have op_index {} after {}, expected {}.",
+                    source.to_base64(base64::STANDARD), transfer.get_op_index(),
+                    previous, previous + 1)));
+            }
+        }
+        last_op_index.insert(source, transfer.get_op_index());
+    }
+    Ok(())
+}
+
+fn is_referenced_signer(commit: &Commitment, public_key: &[u8]) -> bool {
+    if public_key.len() == 0 { return false; }
+    commit.get_bounty_pk() == public_key ||
+        commit.get_transfers().iter().any(|transfer| {
+            transfer.get_source_pk() == public_key ||
+                transfer.get_multisig_pks().iter().any(|pk| &pk[..] == public_key)
+        })
+}
+
+fn verify_transfer_signature(check: &(usize, &[u8], &[u8]),
+                             commit_bytes: &[u8]) -> IroncResult<()> {
+    let (index, public_key_bytes, sign_bytes) = *check;
+    match verify_detached_signature(public_key_bytes, sign_bytes, commit_bytes) {
+        Ok(()) => Ok(()),
+        Err(_) => Err(IroncError::invalid_signature(&format!(
+            "Transfer {} ({}) has an invalid signature.", index,
+            source_address(public_key_bytes))))
+    }
+}
+
+// The full address of a source for error messages, or its base64 encoding if
+// it isn't a valid public key.
+fn source_address(pk_bytes: &[u8]) -> String {
+    match PublicKey::from_slice(pk_bytes) {
+        Ok(public_key) => public_key.to_address(),
+        Err(_) => pk_bytes.to_base64(base64::STANDARD)
+    }
+}
+
+// Each check is (transfer index, source public key, signature).
+#[cfg(not(feature = "parallel"))]
+fn verify_transfer_signatures(checks: &[(usize, &[u8], &[u8])],
+                              commit_bytes: &[u8]) -> IroncResult<()> {
+    for check in checks.iter() {
+        try!(verify_transfer_signature(check, commit_bytes));
+    }
+    Ok(())
+}
+
+// Splits the checks into one contiguous chunk per CPU, each verified on its
+// own thread. The results are put back in chunk order, so the error returned
+// is the one for the first invalid transfer, as in the sequential version.
+// This uses plain threads rather than rayon, which doesn't build with the
+// toolchain this crate targets; thread::scoped is avoided as it's unsound, so
+// each thread gets its own copy of the checks.
+#[cfg(feature = "parallel")]
+fn verify_transfer_signatures(checks: &[(usize, &[u8], &[u8])],
+                              commit_bytes: &[u8]) -> IroncResult<()> {
+    use std::cmp::max;
+    use std::sync::Arc;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use num_cpus;
+
+    let threads = max(1, num_cpus::get());
+    let chunk_size = max(1, (checks.len() + threads - 1) / threads);
+    let commit_bytes = Arc::new(commit_bytes.to_vec());
+    let (sender, receiver) = channel();
+    let mut chunk_count = 0;
+    for (chunk_index, chunk) in checks.chunks(chunk_size).enumerate() {
+        let owned_chunk: Vec<(usize, Vec<u8>, Vec<u8>)> = chunk.iter()
+            .map(|&(index, pk, sign)| (index, pk.to_vec(), sign.to_vec()))
+            .collect();
+        let (sender, commit_bytes) = (sender.clone(), commit_bytes.clone());
+        thread::spawn(move || {
+            let mut result = Ok(());
+            for &(index, ref pk, ref sign) in owned_chunk.iter() {
+                result = verify_transfer_signature(
+                    &(index, &pk[..], &sign[..]), &commit_bytes);
+                if result.is_err() { break; }
+            }
+            let _ = sender.send((chunk_index, result));
+        });
+        chunk_count += 1;
+    }
+    drop(sender);
+
+    let mut results: Vec<(usize, IroncResult<()>)> = receiver.iter().collect();
+    if results.len() < chunk_count {
+        return Err(IroncError::new("A signature verification thread panicked."));
+    }
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+    for (_, result) in results.into_iter() { try!(result); }
+    Ok(())
 }
 
-#[derive(Default)]
+fn verify_detached_signature(public_key_bytes: &[u8], sign_bytes: &[u8],
+                             commit_bytes: &[u8]) -> IroncResult<()> {
+    let public_key = try!(PublicKey::from_slice(public_key_bytes));
+    let signature = try!(Signature::from_slice(sign_bytes));
+    verify_signature(&public_key, commit_bytes, &signature)
+}
+
+fn verify_multisig_transfer(transfer: &Transfer, sign_map: &HashMap<&[u8], &[u8]>,
+                            commit_bytes: &[u8]) -> IroncResult<()> {
+    let threshold = transfer.get_multisig_threshold();
+    let mut signers = Vec::<PublicKey>::new();
+    for pk_bytes in transfer.get_multisig_pks().iter() {
+        let signer = try!(PublicKey::from_slice(pk_bytes));
+        if signers.contains(&signer) {
+            return Err(IroncError::new(&format!(
+                "Multisig signer {} is listed more than once.", signer)));
+        }
+        signers.push(signer);
+    }
+    if threshold == 0 || threshold as usize > signers.len() {
+        return Err(IroncError::new(&format!(
+            "Invalid multisig threshold {} for {} signers.",
+            threshold, signers.len())));
+    }
+    if &multisig_account(threshold, &signers).0[..] != transfer.get_source_pk() {
+        return Err(IroncError::new(
+            "Multisig signers don't match the source account."));
+    }
+    let num_valid = signers.iter().filter(|signer| {
+        match sign_map.get(&signer.0[..]) {
+            Some(sign_bytes) => verify_detached_signature(
+                &signer.0, sign_bytes, commit_bytes).is_ok(),
+            None => false
+        }
+    }).count();
+    if num_valid < threshold as usize {
+        return Err(IroncError::new(&format!(
+            "Multisig transfer has {} valid signatures, {} required.",
+            num_valid, threshold)));
+    }
+    Ok(())
+}
+
+pub fn make_detached_signature(
+    secret_key: &SecretKey, public_key: &PublicKey, commit_bytes: &[u8])
+    -> IroncResult<DetachedSignature> {
+    let signature = sign(secret_key, commit_bytes);
+    match verify_signature(public_key, commit_bytes, &signature) {
+        Ok(_) => {
+            let mut sign = DetachedSignature::new();
+            sign.set_public_key(public_key.0.to_vec());
+            sign.set_payload(signature.0.to_vec());
+            Ok(sign)
+        },
+        Err(_) => Err(IroncError::crypto("Invalid key for source account."))
+    }
+}
+
+fn short_address(pk_bytes: &[u8]) -> String {
+    match PublicKey::from_slice(pk_bytes) {
+        Ok(public_key) => public_key.to_address().chars().take(8).collect(),
+        Err(_) => String::from_str("<invalid>")
+    }
+}
+
+// Number of signatures a fully signed transaction needs: one per distinct
+// source, the threshold for multisig sources, and one for the bounty payer.
+fn num_required_signatures(tx: &Transaction) -> usize {
+    let commit = tx.get_commit();
+    let mut signers: Vec<&[u8]> = vec![];
+    let mut num_multisig = 0;
+    for transfer in commit.get_transfers().iter() {
+        if transfer.get_multisig_pks().len() > 0 {
+            num_multisig += transfer.get_multisig_threshold() as usize;
+        } else if !signers.contains(&transfer.get_source_pk()) {
+            signers.push(transfer.get_source_pk());
+        }
+    }
+    if commit.get_bounty() > 0 && !signers.contains(&commit.get_bounty_pk()) {
+        signers.push(commit.get_bounty_pk());
+    }
+    signers.len() + num_multisig
+}
+
+pub fn pretty_format(tx: &Transaction) -> String {
+    let mut formatted = String::new();
+    let commit = tx.get_commit();
+    formatted.push_str(&format!("[ {} ]\n", match tx.txid() {
+        Ok(txid) => format!("{}", TxId(txid)),
+        Err(_) => String::from_str("<invalid commit>")
+    }));
+    for transfer in commit.get_transfers().iter() {
+        formatted.push_str(&format!(
+            "  {} -> {}: {} (op {})\n", short_address(transfer.get_source_pk()),
+            short_address(transfer.get_destination_pk()), transfer.get_tokens(),
+            transfer.get_op_index()));
+        if transfer.has_memo() {
+            formatted.push_str(&format!("    memo: {}\n", transfer.get_memo()));
+        }
+    }
+    if commit.get_bounty() > 0 {
+        formatted.push_str(&format!(
+            "  bounty: {} (paid by {})\n", commit.get_bounty(),
+            short_address(commit.get_bounty_pk())));
+    } else {
+        formatted.push_str("  bounty: 0\n");
+    }
+    if commit.get_valid_until() != 0 {
+        formatted.push_str(&format!(
+            "  valid until: {}\n", commit.get_valid_until()));
+    }
+    formatted.push_str(&format!(
+        "  signatures: {} attached / {} required\n", tx.get_signatures().len(),
+        num_required_signatures(tx)));
+    formatted
+}
+
+// The protobuf encoding of transactions, without exposing protobuf's types.
+
+pub fn serialize_transaction(tx: &Transaction) -> IroncResult<Vec<u8>> {
+    Ok(try!(tx.write_to_bytes()))
+}
+
+pub fn parse_transaction(bytes: &[u8]) -> IroncResult<Transaction> {
+    Ok(try!(protobuf::parse_from_bytes(bytes)))
+}
+
+// JSON representation of transactions. Byte fields are base64 encoded and
+// unset optional fields are null, so decoding reproduces the exact protobuf
+// (and hence the same txid).
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct TransferJson {
+    op_index: Option<u32>,
+    tokens: Option<u64>,
+    source_pk: Option<String>,
+    destination_pk: Option<String>,
+    multisig_pks: Vec<String>,
+    multisig_threshold: Option<u32>,
+    memo: Option<String>
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct SignatureJson {
+    public_key: Option<String>,
+    payload: Option<String>
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct TransactionJson {
+    tx_type: Option<i32>,
+    bounty_pk: Option<String>,
+    bounty: Option<u64>,
+    chain_id: Option<u32>,
+    valid_until: Option<u64>,
+    transfers: Vec<TransferJson>,
+    signatures: Vec<SignatureJson>
+}
+
+fn bytes_to_json(is_set: bool, bytes: &[u8]) -> Option<String> {
+    if is_set { Some(bytes.to_base64(base64::STANDARD)) } else { None }
+}
+
+fn bytes_from_json(maybe_base64: &Option<String>) -> IroncResult<Option<Vec<u8>>> {
+    match *maybe_base64 {
+        Some(ref encoded) => Ok(Some(try!(FromBase64::from_base64(&encoded[..])))),
+        None => Ok(None)
+    }
+}
+
+pub fn transaction_to_json(tx: &Transaction) -> IroncResult<String> {
+    let commit = tx.get_commit();
+    let transfers = commit.get_transfers().iter().map(|transfer| TransferJson {
+        op_index: if transfer.has_op_index() {
+            Some(transfer.get_op_index()) } else { None },
+        tokens: if transfer.has_tokens() { Some(transfer.get_tokens()) } else { None },
+        source_pk: bytes_to_json(transfer.has_source_pk(), transfer.get_source_pk()),
+        destination_pk: bytes_to_json(transfer.has_destination_pk(),
+                                      transfer.get_destination_pk()),
+        multisig_pks: transfer.get_multisig_pks().iter().map(
+            |pk| pk.to_base64(base64::STANDARD)).collect(),
+        multisig_threshold: if transfer.has_multisig_threshold() {
+            Some(transfer.get_multisig_threshold()) } else { None },
+        memo: if transfer.has_memo() {
+            Some(String::from_str(transfer.get_memo())) } else { None }
+    }).collect();
+    let signatures = tx.get_signatures().iter().map(|sign| SignatureJson {
+        public_key: bytes_to_json(sign.has_public_key(), sign.get_public_key()),
+        payload: bytes_to_json(sign.has_payload(), sign.get_payload())
+    }).collect();
+    let tx_json = TransactionJson {
+        tx_type: if commit.has_tx_type() {
+            Some(commit.get_tx_type().value()) } else { None },
+        bounty_pk: bytes_to_json(commit.has_bounty_pk(), commit.get_bounty_pk()),
+        bounty: if commit.has_bounty() { Some(commit.get_bounty()) } else { None },
+        chain_id: if commit.has_chain_id() {
+            Some(commit.get_chain_id()) } else { None },
+        valid_until: if commit.has_valid_until() {
+            Some(commit.get_valid_until()) } else { None },
+        transfers: transfers,
+        signatures: signatures
+    };
+    Ok(try!(json::encode(&tx_json)))
+}
+
+pub fn transaction_from_json(encoded: &str) -> IroncResult<Transaction> {
+    let tx_json: TransactionJson = try!(json::decode(encoded));
+    let mut tx = Transaction::new();
+    {
+        let commit = tx.mut_commit();
+        if let Some(tx_type) = tx_json.tx_type {
+            commit.set_tx_type(try!(Commitment_Type::from_i32(tx_type).ok_or(
+                IroncError::with_kind(IroncErrorKind::Decode, &format!(
+                    "Unknown transaction type {}", tx_type)))));
+        }
+        if let Some(bounty_pk) = try!(bytes_from_json(&tx_json.bounty_pk)) {
+            commit.set_bounty_pk(bounty_pk);
+        }
+        if let Some(bounty) = tx_json.bounty { commit.set_bounty(bounty); }
+        if let Some(chain_id) = tx_json.chain_id { commit.set_chain_id(chain_id); }
+        if let Some(valid_until) = tx_json.valid_until {
+            commit.set_valid_until(valid_until);
+        }
+        for transfer_json in tx_json.transfers.iter() {
+            let mut transfer = Transfer::new();
+            if let Some(op_index) = transfer_json.op_index {
+                transfer.set_op_index(op_index);
+            }
+            if let Some(tokens) = transfer_json.tokens { transfer.set_tokens(tokens); }
+            if let Some(source_pk) = try!(bytes_from_json(&transfer_json.source_pk)) {
+                transfer.set_source_pk(source_pk);
+            }
+            if let Some(destination_pk) =
+                try!(bytes_from_json(&transfer_json.destination_pk)) {
+                transfer.set_destination_pk(destination_pk);
+            }
+            for pk in transfer_json.multisig_pks.iter() {
+                transfer.mut_multisig_pks().push(
+                    try!(FromBase64::from_base64(&pk[..])));
+            }
+            if let Some(threshold) = transfer_json.multisig_threshold {
+                transfer.set_multisig_threshold(threshold);
+            }
+            if let Some(ref memo) = transfer_json.memo {
+                transfer.set_memo(memo.clone());
+            }
+            commit.mut_transfers().push(transfer);
+        }
+    }
+    for sign_json in tx_json.signatures.iter() {
+        let mut sign = DetachedSignature::new();
+        if let Some(public_key) = try!(bytes_from_json(&sign_json.public_key)) {
+            sign.set_public_key(public_key);
+        }
+        if let Some(payload) = try!(bytes_from_json(&sign_json.payload)) {
+            sign.set_payload(payload);
+        }
+        tx.mut_signatures().push(sign);
+    }
+    Ok(tx)
+}
+
+// Cloning gives a snapshot to build several variants from. SecretKey's Debug
+// is redacted, so debug output doesn't reveal the secret keys.
+#[derive(Clone, Debug, Default)]
 pub struct TransactionBuilder {
-    transfer_secret_keys: Vec<SecretKey>,
+    // The secret keys signing each transfer, empty for unsigned transfers.
+    transfer_secret_keys: Vec<Vec<SecretKey>>,
     bounty_secret_key: Option<SecretKey>,
+    allow_self_transfer: bool,
     commit: Commitment
 }
 
 impl TransactionBuilder {
     pub fn new() -> TransactionBuilder {
         TransactionBuilder {
-            transfer_secret_keys: Vec::<SecretKey>::new(),
+            transfer_secret_keys: Vec::<Vec<SecretKey>>::new(),
             bounty_secret_key: None,
+            allow_self_transfer: false,
             commit: Commitment::new()
         }
     }
 
-    pub fn add_transfer(
-        &mut self, sk: &SecretKey, source: &PublicKey, destination: &PublicKey,
-        tokens: u64, op_index:u32) -> &mut Self {
+    // Mints `tokens` to `destination` (see Commitment.Type.COINBASE).
+    pub fn coinbase(destination: &PublicKey, tokens: u64)
+                    -> IroncResult<Transaction> {
+        if tokens == 0 {
+            return Err(IroncError::new(
+                "A coinbase transaction has to mint some tokens."));
+        }
         let mut transfer = Transfer::new();
+        transfer.set_op_index(0);
+        transfer.set_tokens(tokens);
+        transfer.set_destination_pk(destination.0.to_vec());
+        let mut transaction = Transaction::new();
+        transaction.mut_commit().set_tx_type(Commitment_Type::COINBASE);
+        transaction.mut_commit().mut_transfers().push(transfer);
+        Ok(transaction)
+    }
+
+    // A builder for editing an existing commit. Its transfers and bounty have
+    // no secret keys; supply them with add_secret_key before calling build.
+    pub fn from_commit(commit: Commitment) -> TransactionBuilder {
+        let num_transfers = commit.get_transfers().len();
+        TransactionBuilder {
+            transfer_secret_keys:
+                range(0, num_transfers).map(|_| vec![]).collect(),
+            bounty_secret_key: None,
+            allow_self_transfer: false,
+            commit: commit
+        }
+    }
+
+    // Signs the transfers and the bounty that `sk` is a signer of when the
+    // transaction is built.
+    pub fn add_secret_key(&mut self, sk: &SecretKey) -> &mut Self {
+        let pk = derive_public_key(sk);
+        for (transfer, secret_keys) in self.commit.get_transfers().iter()
+            .zip(self.transfer_secret_keys.iter_mut())
+        {
+            let is_signer = if transfer.get_multisig_pks().len() > 0 {
+                transfer.get_multisig_pks().iter()
+                    .any(|signer| &signer[..] == &pk.0[..])
+            } else {
+                transfer.get_source_pk() == &pk.0[..]
+            };
+            if is_signer && !secret_keys.contains(sk) {
+                secret_keys.push(sk.clone());
+            }
+        }
+        if self.commit.get_bounty() > 0 &&
+            self.commit.get_bounty_pk() == &pk.0[..] {
+            self.bounty_secret_key = Some(sk.clone());
+        }
+        self
+    }
+
+    // Transfers from an account to itself are rejected unless this is set,
+    // as they're usually a mistake.
+    pub fn allow_self_transfer(&mut self, allow: bool) -> &mut Self {
+        self.allow_self_transfer = allow;
+        self
+    }
+
+    fn push_transfer(
+        &mut self, sks: &[SecretKey], mut transfer: Transfer, source: &PublicKey,
+        destination: &PublicKey, tokens: u64, op_index: u32)
+        -> IroncResult<&mut Self> {
+        if source == destination && !self.allow_self_transfer {
+            return Err(IroncError::new(&format!(
+                "Transfer with op_index {} has the same source and destination.",
+                op_index)));
+        }
+        // build signs once per source, so every signed transfer from a source
+        // must come with the same secret key.
+        if transfer.get_multisig_pks().is_empty() {
+            let conflicting = self.commit.get_transfers().iter()
+                .zip(self.transfer_secret_keys.iter())
+                .any(|(earlier, earlier_sks)| {
+                    earlier.get_multisig_pks().is_empty() &&
+                        earlier.get_source_pk() == &source.0[..] &&
+                        earlier_sks.len() > 0 && sks.len() > 0 &&
+                        earlier_sks[0] != sks[0]
+                });
+            if conflicting {
+                return Err(IroncError::new(&format!(
+                    "Transfer with op_index {} has a different secret key than \
+                     an earlier transfer from {}.", op_index, source)));
+            }
+        }
         transfer.set_op_index(op_index);
         transfer.set_tokens(tokens);
+        // PublicKey wraps a [u8; PUBLICKEYBYTES], so both keys always have the
+        // ed25519 length here; malformed bytes are rejected earlier, by
+        // PublicKey::from_slice.
         transfer.mut_source_pk().push_all(&source.0);
         transfer.mut_destination_pk().push_all(&destination.0);
 
-        self.transfer_secret_keys.push(sk.clone());
+        self.transfer_secret_keys.push(sks.to_vec());
         self.commit.mut_transfers().push(transfer);
+        Ok(self)
+    }
+
+    pub fn add_transfer(
+        &mut self, sk: &SecretKey, source: &PublicKey, destination: &PublicKey,
+        tokens: u64, op_index:u32) -> IroncResult<&mut Self> {
+        self.push_transfer(&[sk.clone()], Transfer::new(), source, destination,
+                           tokens, op_index)
+    }
+
+    // The memo is part of the commit, so it's covered by the signature.
+    pub fn add_transfer_with_memo(
+        &mut self, sk: &SecretKey, source: &PublicKey, destination: &PublicKey,
+        tokens: u64, op_index:u32, memo: &str) -> IroncResult<&mut Self> {
+        if memo.len() > MAX_MEMO_BYTES {
+            return Err(IroncError::new(&format!(
+                "Memo of transfer with op_index {} is {} bytes long, the \
+                 maximum is {}.", op_index, memo.len(), MAX_MEMO_BYTES)));
+        }
+        let mut transfer = Transfer::new();
+        transfer.set_memo(String::from_str(memo));
+        self.push_transfer(&[sk.clone()], transfer, source, destination,
+                           tokens, op_index)
+    }
+
+    // Pays a request parsed with payment::parse_payment_uri.
+    pub fn add_payment_request(
+        &mut self, sk: &SecretKey, source: &PublicKey, request: &PaymentRequest,
+        op_index: u32) -> IroncResult<&mut Self> {
+        match request.memo {
+            Some(ref memo) => self.add_transfer_with_memo(
+                sk, source, &request.destination, request.tokens, op_index, memo),
+            None => self.add_transfer(
+                sk, source, &request.destination, request.tokens, op_index)
+        }
+    }
+
+    // Signs with the secret key the wallet has for `source`.
+    pub fn add_transfer_from_wallet(
+        &mut self, wallet: &Wallet, source: &PublicKey, destination: &PublicKey,
+        tokens: u64, op_index:u32) -> IroncResult<&mut Self> {
+        let key = try!(wallet.find_key_by_public_key(source)
+            .ok_or(IroncError::missing_key(&format!(
+                "The wallet doesn't contain the source address {}.", source))));
+        let secret_key = try!(key.decode_secret_key());
+        self.add_transfer(&secret_key, source, destination, tokens, op_index)
+    }
+
+    pub fn add_unsigned_transfer(
+        &mut self, source: &PublicKey, destination: &PublicKey,
+        tokens: u64, op_index:u32) -> IroncResult<&mut Self> {
+        self.push_transfer(&[], Transfer::new(), source, destination,
+                           tokens, op_index)
+    }
+
+    // Transfers from the account controlled by `threshold` of `signers`
+    // (see crypto::multisig_account). `sks` are the secret keys of the
+    // signers available locally; more signatures can be attached later.
+    pub fn add_multisig_transfer(
+        &mut self, sks: &[SecretKey], signers: &[PublicKey], threshold: u32,
+        destination: &PublicKey, tokens: u64, op_index:u32)
+        -> IroncResult<&mut Self> {
+        let mut transfer = Transfer::new();
+        transfer.set_multisig_threshold(threshold);
+        for signer in signers.iter() {
+            transfer.mut_multisig_pks().push(signer.0.to_vec());
+        }
+        let source = multisig_account(threshold, signers);
+        self.push_transfer(sks, transfer, &source, destination, tokens, op_index)
+    }
+
+    // Removes the transfers and the bounty, keeping the chain id.
+    pub fn clear(&mut self) -> &mut Self {
+        self.transfer_secret_keys.clear();
+        self.commit.clear_transfers();
+        self.bounty_secret_key = None;
+        self.commit.clear_bounty_pk();
+        self.commit.clear_bounty();
         self
     }
 
+    // The secret keys of the transfer are removed with it, so the remaining
+    // transfers stay paired with their own keys.
+    pub fn remove_last_transfer(&mut self) -> Option<Transfer> {
+        self.transfer_secret_keys.pop();
+        self.commit.mut_transfers().pop()
+    }
+
     pub fn set_bounty(&mut self, sk: &SecretKey, source: &PublicKey,
                       bounty: u64) -> &mut Self {
+        self.set_unsigned_bounty(source, bounty);
         self.bounty_secret_key = Some(sk.clone());
-        self.commit.mut_bounty_pk().push_all(&source.0);
+        self
+    }
+
+    pub fn set_unsigned_bounty(&mut self, source: &PublicKey,
+                               bounty: u64) -> &mut Self {
+        self.bounty_secret_key = None;
+        self.commit.set_bounty_pk(source.0.to_vec());
         self.commit.set_bounty(bounty);
         self
     }
 
+    pub fn set_chain_id(&mut self, chain_id: u32) -> &mut Self {
+        self.commit.set_chain_id(chain_id);
+        self
+    }
+
+    // See Commitment.valid_until; zero (the default) never expires.
+    pub fn set_valid_until(&mut self, valid_until: u64) -> &mut Self {
+        self.commit.set_valid_until(valid_until);
+        self
+    }
+
+    // The fee is paid as the commit's bounty; this is an alias for set_bounty.
+    pub fn set_fee(&mut self, sk: &SecretKey, source: &PublicKey,
+                   fee: u64) -> &mut Self {
+        self.set_bounty(sk, source, fee)
+    }
+
+    pub fn total_transferred(&self) -> IroncResult<u64> {
+        let mut total = 0u64;
+        for transfer in self.commit.get_transfers().iter() {
+            total = try!(total.checked_add(transfer.get_tokens())
+                         .ok_or(IroncError::token_overflow()));
+        }
+        Ok(total)
+    }
+
+    // The serialized size of the fully signed transaction, for size based
+    // fees. All signatures have the same length, so this is exact once a
+    // signature is attached for each signer (see num_required_signatures).
+    pub fn estimated_size(&self) -> IroncResult<usize> {
+        let mut transaction = Transaction::new();
+        transaction.set_commit(self.commit.clone());
+        let placeholder_pk = PublicKey([0; 32]);
+        let placeholder_sign = Signature([0; 64]);
+        for _ in range(0, num_required_signatures(&transaction)) {
+            transaction.attach_signature(&placeholder_pk, &placeholder_sign);
+        }
+        transaction.serialized_size()
+    }
+
+    // A bounty of `fee_per_byte` times estimated_size, to pass to set_bounty.
+    // The size is taken with the largest possible bounty, so the suggestion
+    // still covers the transaction once the bounty is set. Set the bounty
+    // payer beforehand (e.g. with a bounty of 1) so that its signature is
+    // counted too.
+    pub fn suggest_bounty(&self, fee_per_byte: u64) -> IroncResult<u64> {
+        let mut sized = self.clone();
+        if sized.commit.has_bounty_pk() { sized.commit.set_bounty(u64::MAX); }
+        let size = try!(sized.estimated_size()) as u64;
+        size.checked_mul(fee_per_byte).ok_or(IroncError::token_overflow())
+    }
+
+    fn check_commit(&self) -> IroncResult<()> {
+        if self.commit.get_transfers().is_empty() && self.commit.get_bounty() == 0 {
+            return Err(IroncError::new("Transaction has no transfers."));
+        }
+        // A zero bounty is fine, but a transfer has to move some tokens.
+        let mut total_tokens = self.commit.get_bounty();
+        for transfer in self.commit.get_transfers().iter() {
+            if transfer.get_tokens() == 0 {
+                return Err(IroncError::new(&format!(
+                    "Transfer with op_index {} doesn't transfer any tokens.",
+                    transfer.get_op_index())));
+            }
+            total_tokens = try!(total_tokens.checked_add(transfer.get_tokens())
+                .ok_or(IroncError::token_overflow()));
+        }
+        try!(check_op_indices(&self.commit));
+        Ok(())
+    }
+
+    // Builds a transaction without any signatures, for signing offline.
+    pub fn build_unsigned(self) -> IroncResult<Transaction> {
+        try!(self.check_commit());
+        let mut transaction = Transaction::new();
+        transaction.set_commit(self.commit);
+        Ok(transaction)
+    }
+
     pub fn build(self) -> IroncResult<Transaction> {
+        try!(self.check_commit());
         let mut transaction = Transaction::new();
-        let commit_bytes = &self.commit.write_to_bytes().unwrap();
-        for (transfer, secret_key) in self.commit.get_transfers().iter()
+        let commit_bytes = &try!(self.commit.signing_bytes());
+        // The signature covers the whole commit, so each distinct source only
+        // needs to sign once.
+        for (transfer, secret_keys) in self.commit.get_transfers().iter()
             .zip(self.transfer_secret_keys.iter())
         {
-            let signature = sign(secret_key, commit_bytes);
-            let pk = try!(PublicKey::from_slice(transfer.get_source_pk()));
-            match verify_signature(&pk, commit_bytes, &signature) {
-                Ok(_) => {
-                    let mut sign = DetachedSignature::new();
-                    sign.set_public_key(pk.0.to_vec());
-                    sign.set_payload(signature.0.to_vec());
-                    transaction.mut_signatures().push(sign);
-                },
-                Err(_) => return Err(
-                    IroncError::new("Invalid key for source account."))
+            for secret_key in secret_keys.iter() {
+                let pk = if transfer.get_multisig_pks().len() > 0 {
+                    derive_public_key(secret_key)
+                } else {
+                    try!(PublicKey::from_slice(transfer.get_source_pk()))
+                };
+                let already_signed = transaction.get_signatures().iter().any(
+                    |sign| sign.get_public_key() == &pk.0[..]);
+                if already_signed { continue; }
+                transaction.mut_signatures().push(
+                    try!(make_detached_signature(secret_key, &pk, commit_bytes)));
+            }
+        }
+        if let Some(ref bounty_sk) = self.bounty_secret_key {
+            let bounty_pk = try!(PublicKey::from_slice(self.commit.get_bounty_pk()));
+            let already_signed = transaction.get_signatures().iter().any(
+                |sign| sign.get_public_key() == self.commit.get_bounty_pk());
+            if !already_signed {
+                transaction.mut_signatures().push(
+                    try!(make_detached_signature(bounty_sk, &bounty_pk, commit_bytes)));
             }
         }
+        let chain_id = self.commit.get_chain_id();
         transaction.set_commit(self.commit);
-        try!(transaction.verify_signatures());
+        try!(transaction.verify_signatures(chain_id));
         Ok(transaction)
     }
 }
+
+/*****  Tests  *****/
+
+use crypto::gen_keypair;
+
+#[test]
+fn test_txid_ignores_signature_order() {
+    use protobuf::RepeatedField;
+
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap();
+    tx_builder.add_transfer(&sk2, &pk2, &pk1, 3, 0).unwrap();
+    let tx = tx_builder.build().unwrap();
+
+    let mut reordered = tx.clone();
+    let mut signatures = tx.get_signatures().to_vec();
+    signatures.reverse();
+    reordered.set_signatures(RepeatedField::from_vec(signatures));
+    assert!(tx != reordered);
+    assert_eq!(tx.txid().unwrap(), reordered.txid().unwrap());
+    assert_eq!(tx.txid().unwrap(), tx.clone().txid().unwrap());
+    assert_eq!(hash(&tx.get_commit().write_to_bytes().unwrap()).0,
+               tx.txid().unwrap());
+
+    let mut other_commit = tx.clone();
+    other_commit.mut_commit().set_bounty(1);
+    assert!(tx.txid().unwrap() != other_commit.txid().unwrap());
+}
+
+#[test]
+fn test_transaction_builder_fee_and_total() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    assert_eq!(0, tx_builder.total_transferred().unwrap());
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap()
+        .add_transfer(&sk2, &pk2, &pk1, 32, 0).unwrap()
+        .set_fee(&sk1, &pk1, 2);
+    assert_eq!(42, tx_builder.total_transferred().unwrap());
+    assert_eq!(2, tx_builder.build().unwrap().fee());
+}
+
+#[test]
+fn test_transaction_builder_rejects_overflow() {
+    use std::u64;
+
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, u64::MAX, 0).unwrap()
+        .add_transfer(&sk2, &pk2, &pk1, 1, 0).unwrap();
+    assert_eq!(Err(IroncError::token_overflow()), tx_builder.total_transferred());
+    assert_eq!(Err(IroncError::token_overflow()), tx_builder.build());
+}
+
+#[test]
+fn test_verify_signatures_requires_bounty_signature() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap()
+        .set_bounty(&sk2, &pk2, 1);
+    let mut tx = tx_builder.build().unwrap();
+    assert_eq!(2, tx.get_signatures().len());
+
+    tx.mut_signatures().pop();
+    assert_eq!(Err(IroncError::missing_key(
+        "Missing signature for the bounty public key.")),
+               tx.verify_signatures(DEFAULT_CHAIN_ID));
+}
+
+#[test]
+fn test_transaction_builder_one_signature_per_source() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let (pk3, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap()
+        .add_transfer(&sk1, &pk1, &pk3, 5, 1).unwrap()
+        .set_bounty(&sk1, &pk1, 1);
+    let tx = tx_builder.build().unwrap();
+    assert_eq!(1, tx.get_signatures().len());
+    assert!(tx.verify_signatures(DEFAULT_CHAIN_ID).is_ok());
+}
+
+#[test]
+fn test_transaction_builder_rejects_conflicting_source_keys() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap();
+    assert_eq!(Err(IroncError::new(&format!(
+        "Transfer with op_index 1 has a different secret key than an earlier \
+         transfer from {}.", pk1))),
+        tx_builder.add_transfer(&sk2, &pk1, &pk2, 5, 1).map(|_| ()));
+    tx_builder.add_unsigned_transfer(&pk1, &pk2, 5, 1).unwrap();
+    assert_eq!(2, tx_builder.build_unsigned().unwrap()
+               .get_commit().get_transfers().len());
+}
+
+#[test]
+fn test_transaction_builder_rejects_empty() {
+    assert_eq!(Err(IroncError::new("Transaction has no transfers.")),
+               TransactionBuilder::new().build());
+}
+
+#[test]
+fn test_offline_signing() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_unsigned_transfer(&pk1, &pk2, 10, 0).unwrap()
+        .set_unsigned_bounty(&pk1, 1);
+    let mut tx = tx_builder.build_unsigned().unwrap();
+    assert_eq!(0, tx.get_signatures().len());
+    assert!(tx.verify_signatures(DEFAULT_CHAIN_ID).is_err());
+
+    let signature = sign(&sk1, &tx.commit_bytes().unwrap());
+    tx.attach_signature(&pk1, &signature);
+    assert!(tx.verify_signatures(DEFAULT_CHAIN_ID).is_ok());
+}
+
+#[test]
+fn test_multisig_transfer_2_of_3() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let (pk3, _) = gen_keypair();
+    let (destination, _) = gen_keypair();
+    let signers = [pk1.clone(), pk2.clone(), pk3.clone()];
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_multisig_transfer(
+        &[sk1.clone(), sk2.clone()], &signers, 2, &destination, 10, 0)
+        .unwrap();
+    let mut tx = tx_builder.build().unwrap();
+    assert_eq!(2, tx.get_signatures().len());
+    assert!(tx.verify_signatures(DEFAULT_CHAIN_ID).is_ok());
+
+    tx.mut_signatures().pop();
+    assert_eq!(Err(IroncError::new(
+        "Multisig transfer has 1 valid signatures, 2 required.")),
+        tx.verify_signatures(DEFAULT_CHAIN_ID));
+
+    let mut one_signer = TransactionBuilder::new();
+    one_signer.add_multisig_transfer(&[sk1], &signers, 2, &destination, 10, 0)
+        .unwrap();
+    assert!(one_signer.build().is_err());
+}
+
+#[test]
+fn test_multisig_transfer_rejects_wrong_signers() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let (destination, _) = gen_keypair();
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_multisig_transfer(
+        &[sk1], &[pk1.clone(), pk2], 1, &destination, 10, 0).unwrap();
+    let mut tx = tx_builder.build().unwrap();
+    tx.mut_commit().mut_transfers()[0].set_multisig_pks(
+        ::protobuf::RepeatedField::from_vec(vec![pk1.0.to_vec()]));
+    assert!(tx.verify_signatures(DEFAULT_CHAIN_ID).is_err());
+}
+
+#[test]
+fn test_transaction_json_round_trip() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap()
+        .add_multisig_transfer(&[sk2], &[pk2.clone()], 1, &pk1, 3, 0).unwrap()
+        .set_bounty(&sk1, &pk1, 1);
+    let tx = tx_builder.build().unwrap();
+
+    let encoded = transaction_to_json(&tx).unwrap();
+    let decoded = transaction_from_json(&encoded).unwrap();
+    assert_eq!(tx, decoded);
+    assert_eq!(tx.txid().unwrap(), decoded.txid().unwrap());
+    assert!(decoded.verify_signatures(DEFAULT_CHAIN_ID).is_ok());
+
+    assert!(transaction_from_json("{}").is_err());
+    assert!(transaction_from_json("not json").is_err());
+}
+
+#[test]
+fn test_pretty_format() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap()
+        .set_bounty(&sk1, &pk1, 2);
+    let formatted = pretty_format(&tx_builder.build().unwrap());
+
+    let src: String = pk1.to_address().chars().take(8).collect();
+    let dst: String = pk2.to_address().chars().take(8).collect();
+    assert!(formatted.contains(&format!("  {} -> {}: 10 (op 0)\n", src, dst)));
+    assert!(formatted.contains(&format!("  bounty: 2 (paid by {})\n", src)));
+    assert!(formatted.contains("  signatures: 1 attached / 1 required\n"));
+}
+
+#[test]
+fn test_verify_signatures_rejects_orphan_signature() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let (pk3, sk3) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap();
+    let mut tx = tx_builder.build().unwrap();
+    assert!(tx.verify_signatures(DEFAULT_CHAIN_ID).is_ok());
+
+    let orphan = sign(&sk3, &tx.commit_bytes().unwrap());
+    tx.attach_signature(&pk3, &orphan);
+    assert!(tx.verify_signatures(DEFAULT_CHAIN_ID).is_err());
+}
+
+#[test]
+fn test_transaction_builder_rejects_zero_tokens() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 0, 0).unwrap();
+    assert!(tx_builder.build().is_err());
+
+    let mut zero_bounty = TransactionBuilder::new();
+    zero_bounty.add_transfer(&sk1, &pk1, &pk2, 1, 0).unwrap()
+        .set_bounty(&sk1, &pk1, 0);
+    assert!(zero_bounty.build().is_ok());
+}
+
+#[test]
+fn test_transaction_builder_checks_op_indices() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 4).unwrap()
+        .add_transfer(&sk2, &pk2, &pk1, 10, 4).unwrap()
+        .add_transfer(&sk1, &pk1, &pk2, 10, 5).unwrap();
+    assert!(tx_builder.build().is_ok());
+
+    let mut duplicate = TransactionBuilder::new();
+    duplicate.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap()
+        .add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap();
+    assert!(duplicate.build().is_err());
+
+    let mut gap = TransactionBuilder::new();
+    gap.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap()
+        .add_transfer(&sk1, &pk1, &pk2, 10, 2).unwrap();
+    assert!(gap.build().is_err());
+}
+
+#[test]
+fn test_transaction_expiry() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap();
+    let never = tx_builder.clone().build().unwrap();
+    assert!(!never.is_expired(u64::MAX));
+    assert!(never.verify_signatures_at(DEFAULT_CHAIN_ID, u64::MAX).is_ok());
+
+    tx_builder.set_valid_until(1000);
+    let tx = tx_builder.build().unwrap();
+    assert!(!tx.is_expired(1000));
+    assert!(tx.is_expired(1001));
+    assert!(tx.verify_signatures_at(DEFAULT_CHAIN_ID, 1000).is_ok());
+    assert_eq!(Err(IroncError::new("Transaction expired at 1000 (now 1001).")),
+               tx.verify_signatures_at(DEFAULT_CHAIN_ID, 1001));
+    assert_eq!(tx, transaction_from_json(&transaction_to_json(&tx).unwrap())
+               .unwrap());
+
+    // valid_until is signed, so it can't be extended.
+    let mut extended = tx.clone();
+    extended.mut_commit().set_valid_until(2000);
+    assert!(extended.verify_signatures_at(DEFAULT_CHAIN_ID, 1001).is_err());
+}
+
+#[test]
+fn test_verify_signatures_checks_chain_id() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap().set_chain_id(7);
+    let tx = tx_builder.build().unwrap();
+    assert!(tx.verify_signatures(7).is_ok());
+    assert!(tx.verify_signatures(DEFAULT_CHAIN_ID).is_err());
+
+    // Changing the chain id invalidates the signatures.
+    let mut replayed = tx.clone();
+    replayed.mut_commit().set_chain_id(DEFAULT_CHAIN_ID);
+    assert!(replayed.verify_signatures(DEFAULT_CHAIN_ID).is_err());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap();
+    let tx = tx_builder.build().unwrap();
+    assert!(!tx.get_commit().has_chain_id());
+    assert!(tx.verify_signatures(DEFAULT_CHAIN_ID).is_ok());
+}
+
+#[test]
+fn test_transfer_public_keys_have_ed25519_length() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    assert!(PublicKey::from_slice(&pk1.0[1..]).is_err());
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap();
+    let tx = tx_builder.build().unwrap();
+    let transfer = &tx.get_commit().get_transfers()[0];
+    assert_eq!(32, transfer.get_source_pk().len());
+    assert_eq!(32, transfer.get_destination_pk().len());
+}
+
+#[test]
+fn test_verify_signatures_error_kinds() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap();
+    let mut tx = tx_builder.build().unwrap();
+
+    let forged = sign(&sk2, &tx.commit_bytes().unwrap());
+    tx.mut_signatures()[0].set_payload(forged.0.to_vec());
+    assert_eq!(IroncErrorKind::InvalidSignature,
+               tx.verify_signatures(DEFAULT_CHAIN_ID).unwrap_err().kind());
+
+    tx.mut_signatures().clear();
+    assert_eq!(IroncErrorKind::MissingKey,
+               tx.verify_signatures(DEFAULT_CHAIN_ID).unwrap_err().kind());
+}
+
+#[test]
+fn test_transaction_builder_add_transfer_from_wallet() {
+    let mut wallet = Wallet::new();
+    let source = wallet.generate_new_key("source").unwrap()
+        .decode_public_key().unwrap();
+    let (destination, _) = gen_keypair();
+    wallet.add_public_key("destination", &destination).unwrap();
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer_from_wallet(&wallet, &source, &destination, 10, 0)
+        .unwrap();
+    let tx = tx_builder.build().unwrap();
+    assert!(tx.verify_signatures(DEFAULT_CHAIN_ID).is_ok());
+
+    let mut tx_builder = TransactionBuilder::new();
+    assert!(tx_builder.add_transfer_from_wallet(
+        &wallet, &destination, &source, 10, 0).is_err());
+    assert!(tx_builder.add_transfer_from_wallet(
+        &Wallet::new(), &source, &destination, 10, 0).is_err());
+}
+
+#[test]
+fn test_verify_signatures_identifies_invalid_transfer() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    for op_index in range(0, 8) {
+        tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, op_index).unwrap();
+    }
+    tx_builder.add_transfer(&sk2, &pk2, &pk1, 10, 0).unwrap();
+    let mut tx = tx_builder.build().unwrap();
+    assert!(tx.verify_signatures(DEFAULT_CHAIN_ID).is_ok());
+
+    let forged = sign(&sk1, &tx.commit_bytes().unwrap());
+    tx.mut_signatures()[1].set_payload(forged.0.to_vec());
+    let err = tx.verify_signatures(DEFAULT_CHAIN_ID).unwrap_err();
+    assert_eq!(IroncErrorKind::InvalidSignature, err.kind());
+    assert_eq!(IroncError::invalid_signature(&format!(
+        "Transfer 8 ({}) has an invalid signature.", pk2.to_address())), err);
+}
+
+#[test]
+fn test_verify_signatures_reports_missing_transfer_signature() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let (pk3, sk3) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap()
+        .add_transfer(&sk2, &pk2, &pk3, 10, 0).unwrap()
+        .add_transfer(&sk3, &pk3, &pk1, 10, 0).unwrap();
+    let mut tx = tx_builder.build().unwrap();
+    tx.mut_signatures().remove(2);
+    assert_eq!(Err(IroncError::missing_key(&format!(
+        "Transfer 2 ({}) is missing a signature.", pk3.to_address()))),
+               tx.verify_signatures(DEFAULT_CHAIN_ID));
+}
+
+#[test]
+fn test_unsigned_sources() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let (pk3, sk3) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_unsigned_transfer(&pk1, &pk2, 10, 0).unwrap()
+        .add_unsigned_transfer(&pk1, &pk2, 10, 1).unwrap()
+        .set_unsigned_bounty(&pk3, 1);
+    let mut tx = tx_builder.build_unsigned().unwrap();
+    assert!(!tx.is_fully_signed());
+    assert_eq!(vec![pk1.clone(), pk3.clone()], tx.unsigned_sources().unwrap());
+
+    let commit_bytes = tx.commit_bytes().unwrap();
+    tx.attach_signature(&pk1, &sign(&sk3, &commit_bytes));
+    assert_eq!(vec![pk1.clone(), pk3.clone()], tx.unsigned_sources().unwrap());
+
+    tx.mut_signatures().clear();
+    tx.attach_signature(&pk1, &sign(&sk1, &commit_bytes));
+    assert_eq!(vec![pk3.clone()], tx.unsigned_sources().unwrap());
+    tx.attach_signature(&pk3, &sign(&sk3, &commit_bytes));
+    assert!(tx.unsigned_sources().unwrap().is_empty());
+    assert!(tx.is_fully_signed());
+}
+
+#[test]
+fn test_unsigned_sources_multisig() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let (pk3, _) = gen_keypair();
+    let (destination, _) = gen_keypair();
+    let signers = [pk1.clone(), pk2.clone(), pk3.clone()];
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_multisig_transfer(&[], &signers, 2, &destination, 10, 0)
+        .unwrap();
+    let mut tx = tx_builder.build_unsigned().unwrap();
+    let commit_bytes = tx.commit_bytes().unwrap();
+    tx.attach_signature(&pk1, &sign(&sk1, &commit_bytes));
+    assert_eq!(vec![pk2.clone(), pk3.clone()], tx.unsigned_sources().unwrap());
+
+    tx.attach_signature(&pk2, &sign(&sk2, &commit_bytes));
+    assert!(tx.unsigned_sources().unwrap().is_empty());
+    assert!(tx.is_fully_signed());
+}
+
+#[test]
+fn test_merge_signatures() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let (pk3, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_unsigned_transfer(&pk1, &pk3, 10, 0).unwrap()
+        .add_unsigned_transfer(&pk2, &pk3, 10, 0).unwrap();
+    let unsigned = tx_builder.build_unsigned().unwrap();
+    let commit_bytes = unsigned.commit_bytes().unwrap();
+
+    let mut signed1 = unsigned.clone();
+    signed1.attach_signature(&pk1, &sign(&sk1, &commit_bytes));
+    let mut signed2 = unsigned.clone();
+    signed2.attach_signature(&pk1, &sign(&sk1, &commit_bytes));
+    signed2.attach_signature(&pk2, &sign(&sk2, &commit_bytes));
+
+    signed1.merge_signatures(&signed2).unwrap();
+    assert_eq!(2, signed1.get_signatures().len());
+    assert!(signed1.verify_signatures(DEFAULT_CHAIN_ID).is_ok());
+
+    let mut other_builder = TransactionBuilder::new();
+    other_builder.add_unsigned_transfer(&pk1, &pk3, 11, 0).unwrap();
+    let other = other_builder.build_unsigned().unwrap();
+    assert!(signed1.merge_signatures(&other).is_err());
+    assert_eq!(2, signed1.get_signatures().len());
+}
+
+#[test]
+fn test_transaction_pubkeys_and_net_delta() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let (pk3, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap()
+        .add_transfer(&sk1, &pk1, &pk3, 5, 1).unwrap()
+        .add_transfer(&sk2, &pk2, &pk3, 3, 0).unwrap()
+        .set_bounty(&sk2, &pk2, 1);
+    let tx = tx_builder.build().unwrap();
+    assert_eq!(vec![pk1.clone(), pk2.clone()], tx.source_pubkeys().unwrap());
+    assert_eq!(vec![pk2.clone(), pk3.clone()], tx.destination_pubkeys().unwrap());
+
+    let deltas = tx.net_delta().unwrap();
+    assert_eq!(3, deltas.len());
+    assert_eq!(Some(&-15), deltas.get(&pk1));
+    assert_eq!(Some(&6), deltas.get(&pk2));
+    assert_eq!(Some(&8), deltas.get(&pk3));
+}
+
+#[test]
+fn test_transaction_builder_rejects_self_transfer() {
+    let (pk1, sk1) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    assert_eq!(Err(IroncError::new(
+        "Transfer with op_index 0 has the same source and destination.")),
+               tx_builder.add_transfer(&sk1, &pk1, &pk1, 10, 0).map(|_| ()));
+    assert!(tx_builder.add_unsigned_transfer(&pk1, &pk1, 10, 0).is_err());
+
+    tx_builder.allow_self_transfer(true);
+    tx_builder.add_transfer(&sk1, &pk1, &pk1, 10, 0).unwrap();
+    let tx = tx_builder.build().unwrap();
+    assert!(tx.verify_signatures(DEFAULT_CHAIN_ID).is_ok());
+}
+
+#[test]
+fn test_signing_bytes() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap();
+    let tx = tx_builder.build().unwrap();
+
+    let signing_bytes = tx.get_commit().signing_bytes().unwrap();
+    let commit_bytes = tx.get_commit().write_to_bytes().unwrap();
+    assert_eq!(SIGNING_VERSION, signing_bytes[0]);
+    assert_eq!(SIGNING_DOMAIN, &signing_bytes[1..1 + SIGNING_DOMAIN.len()]);
+    assert_eq!(&commit_bytes[..], &signing_bytes[1 + SIGNING_DOMAIN.len()..]);
+
+    // A signature over the bare commit isn't accepted.
+    let mut bare = tx.clone();
+    bare.mut_signatures().clear();
+    bare.attach_signature(&pk1, &sign(&sk1, &commit_bytes));
+    assert!(bare.verify_signatures(DEFAULT_CHAIN_ID).is_err());
+    assert!(tx.verify_signatures(DEFAULT_CHAIN_ID).is_ok());
+}
+
+#[test]
+fn test_transfers_merkle_root() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    for op_index in range(0, 5) {
+        tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, op_index).unwrap();
+    }
+    let commit = tx_builder.build().unwrap().get_commit().clone();
+    let transfers = commit.get_transfers();
+    let leaves: Vec<[u8; 32]> = transfers.iter()
+        .map(|transfer| merkle_leaf(transfer).unwrap()).collect();
+
+    // With 5 leaves the last one is paired with itself on every level.
+    let expected_root = merkle_root(5, &merkle_node(
+        &merkle_node(&merkle_node(&leaves[0], &leaves[1]),
+                     &merkle_node(&leaves[2], &leaves[3])),
+        &merkle_node(&merkle_node(&leaves[4], &leaves[4]),
+                     &merkle_node(&leaves[4], &leaves[4]))));
+    let root = commit.transfers_merkle_root().unwrap();
+    assert_eq!(expected_root, root);
+
+    for index in range(0, transfers.len()) {
+        let proof = commit.merkle_proof(index).unwrap();
+        // The self pairings of the last leaf aren't part of its proof.
+        assert_eq!(if index == 4 { 1 } else { 3 }, proof.len());
+        assert!(verify_merkle_proof(
+            &root, &transfers[index], index, 5, &proof).unwrap());
+        assert!(!verify_merkle_proof(
+            &root, &transfers[(index + 1) % 5], index, 5, &proof).unwrap());
+    }
+    assert!(commit.merkle_proof(5).is_err());
+
+    // A proof only verifies at its own index and with the right count.
+    let proof = commit.merkle_proof(4).unwrap();
+    for index in range(0, 8).filter(|index| *index != 4) {
+        assert!(!verify_merkle_proof(
+            &root, &transfers[4], index, 5, &proof).unwrap());
+        assert!(!verify_merkle_proof(
+            &root, &transfers[4], index, 8, &proof).unwrap());
+    }
+    let mut padded = vec![leaves[4].clone(), leaves[4].clone()];
+    padded.push_all(&proof);
+    assert!(!verify_merkle_proof(&root, &transfers[4], 4, 5, &padded).unwrap());
+    assert!(!verify_merkle_proof(&root, &transfers[4], 5, 8, &padded).unwrap());
+    assert_eq!(hash(&[]).0, Commitment::new().transfers_merkle_root().unwrap());
+}
+
+#[test]
+fn test_coinbase() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    assert!(TransactionBuilder::coinbase(&pk1, 0).is_err());
+
+    let coinbase = TransactionBuilder::coinbase(&pk1, 100).unwrap();
+    assert!(coinbase.is_coinbase());
+    assert_eq!(vec![pk1.clone()], coinbase.destination_pubkeys().unwrap());
+    assert!(coinbase.verify_signatures(DEFAULT_CHAIN_ID).is_ok());
+
+    let mut signed = coinbase.clone();
+    signed.attach_signature(&pk1, &sign(&sk1, &signed.commit_bytes().unwrap()));
+    assert!(signed.verify_signatures(DEFAULT_CHAIN_ID).is_err());
+
+    let mut with_source = coinbase.clone();
+    with_source.mut_commit().mut_transfers()[0].set_source_pk(pk2.0.to_vec());
+    assert!(with_source.verify_signatures(DEFAULT_CHAIN_ID).is_err());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap();
+    assert!(!tx_builder.build().unwrap().is_coinbase());
+}
+
+#[test]
+fn test_transaction_builder_add_transfer_with_memo() {
+    use std::iter::repeat;
+
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer_with_memo(&sk1, &pk1, &pk2, 10, 0, "invoice 42")
+        .unwrap();
+    let tx = tx_builder.build().unwrap();
+    assert_eq!("invoice 42", tx.get_commit().get_transfers()[0].get_memo());
+    assert!(pretty_format(&tx).contains("    memo: invoice 42\n"));
+    assert_eq!(tx, transaction_from_json(
+        &transaction_to_json(&tx).unwrap()).unwrap());
+
+    let mut tampered = tx.clone();
+    tampered.mut_commit().mut_transfers()[0].set_memo(
+        String::from_str("invoice 43"));
+    assert!(tampered.verify_signatures(DEFAULT_CHAIN_ID).is_err());
+
+    let max_memo: String = repeat('x').take(MAX_MEMO_BYTES).collect();
+    let mut tx_builder = TransactionBuilder::new();
+    assert!(tx_builder.add_transfer_with_memo(
+        &sk1, &pk1, &pk2, 10, 0, &max_memo).is_ok());
+    assert_eq!(Err(IroncError::new(&format!(
+        "Memo of transfer with op_index 1 is {} bytes long, the maximum is {}.",
+        MAX_MEMO_BYTES + 1, MAX_MEMO_BYTES))),
+        tx_builder.add_transfer_with_memo(
+            &sk1, &pk1, &pk2, 10, 1, &format!("{}x", max_memo)).map(|_| ()));
+}
+
+#[test]
+fn test_transaction_builder_estimated_size() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 3, 1).unwrap()
+        .set_bounty(&sk2, &pk2, 1);
+    let estimated_size = tx_builder.estimated_size().unwrap();
+    let tx = tx_builder.build().unwrap();
+    assert_eq!(2, tx.get_signatures().len());
+    assert_eq!(tx.write_to_bytes().unwrap().len(), tx.serialized_size().unwrap());
+    assert_eq!(tx.serialized_size().unwrap(), estimated_size);
+}
+
+#[test]
+fn test_transaction_builder_suggest_bounty() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap()
+        .set_bounty(&sk1, &pk1, 1);
+    let one_transfer = tx_builder.suggest_bounty(2).unwrap();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 1).unwrap();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 2).unwrap();
+    let three_transfers = tx_builder.suggest_bounty(2).unwrap();
+    assert!(three_transfers > one_transfer);
+    assert_eq!(0, tx_builder.suggest_bounty(0).unwrap());
+    assert_eq!(3 * three_transfers, tx_builder.suggest_bounty(6).unwrap());
+    assert_eq!(IroncError::token_overflow(),
+               tx_builder.suggest_bounty(u64::MAX).unwrap_err());
+
+    // The suggestion is enough to pay for the transaction with the bounty set.
+    tx_builder.set_bounty(&sk1, &pk1, three_transfers);
+    let tx = tx_builder.build().unwrap();
+    assert!(2 * tx.serialized_size().unwrap() as u64 <= three_transfers);
+}
+
+#[test]
+fn test_transaction_builder_add_payment_request() {
+    use payment::parse_payment_uri;
+
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let request = parse_payment_uri(&format!(
+        "ironcoin:{}?amount=12&memo=order%2017", pk2.to_address())).unwrap();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_payment_request(&sk1, &pk1, &request, 0).unwrap();
+    let tx = tx_builder.build().unwrap();
+    let transfer = &tx.get_commit().get_transfers()[0];
+    assert_eq!(&pk2.0[..], transfer.get_destination_pk());
+    assert_eq!(12, transfer.get_tokens());
+    assert_eq!("order 17", transfer.get_memo());
+}
+
+#[test]
+fn test_transaction_builder_remove_last_transfer() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let (pk3, sk3) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    assert_eq!(None, tx_builder.remove_last_transfer());
+    tx_builder.add_transfer(&sk1, &pk1, &pk3, 10, 0).unwrap();
+    tx_builder.add_transfer(&sk2, &pk2, &pk3, 20, 0).unwrap();
+    let removed = tx_builder.remove_last_transfer().unwrap();
+    assert_eq!(&pk2.0[..], removed.get_source_pk());
+    tx_builder.add_transfer(&sk3, &pk3, &pk1, 5, 0).unwrap();
+
+    let tx = tx_builder.build().unwrap();
+    assert_eq!(2, tx.get_commit().get_transfers().len());
+    let signers: Vec<&[u8]> = tx.get_signatures().iter()
+        .map(|sign| sign.get_public_key()).collect();
+    assert_eq!(vec![&pk1.0[..], &pk3.0[..]], signers);
+    assert!(tx.verify_signatures(DEFAULT_CHAIN_ID).is_ok());
+}
+
+#[test]
+fn test_transaction_builder_clear() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.set_chain_id(3);
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap()
+        .set_bounty(&sk1, &pk1, 1);
+    tx_builder.clear();
+    assert_eq!(0, tx_builder.total_transferred().unwrap());
+    assert!(tx_builder.commit.get_transfers().is_empty());
+    assert!(tx_builder.transfer_secret_keys.is_empty());
+    assert!(!tx_builder.commit.has_bounty_pk());
+
+    tx_builder.add_transfer(&sk2, &pk2, &pk1, 4, 0).unwrap();
+    let tx = tx_builder.build().unwrap();
+    assert_eq!(0, tx.fee());
+    assert_eq!(1, tx.get_signatures().len());
+    assert_eq!(3, tx.get_commit().get_chain_id());
+}
+
+#[test]
+fn test_transaction_builder_from_commit() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap()
+        .set_bounty(&sk2, &pk2, 1);
+    let tx = tx_builder.build().unwrap();
+    let txid = tx.txid().unwrap();
+
+    let commit = tx.into_unsigned_commit();
+    let mut tx_builder = TransactionBuilder::from_commit(commit.clone());
+    assert!(TransactionBuilder::from_commit(commit).build().is_err());
+    tx_builder.add_secret_key(&sk1).add_secret_key(&sk2);
+    let resigned = tx_builder.build().unwrap();
+    assert_eq!(txid, resigned.txid().unwrap());
+    assert_eq!(2, resigned.get_signatures().len());
+
+    let mut tx_builder =
+        TransactionBuilder::from_commit(resigned.into_unsigned_commit());
+    tx_builder.add_secret_key(&sk1).add_secret_key(&sk2);
+    tx_builder.add_transfer(&sk2, &pk2, &pk1, 3, 0).unwrap();
+    let edited = tx_builder.build().unwrap();
+    assert_eq!(2, edited.get_commit().get_transfers().len());
+    assert_eq!(2, edited.get_signatures().len());
+}
+
+#[test]
+fn test_transaction_builder_clone() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let (pk3, _) = gen_keypair();
+    let mut base = TransactionBuilder::new();
+    base.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap();
+
+    let mut variant = base.clone();
+    variant.add_transfer(&sk1, &pk1, &pk3, 5, 1).unwrap();
+    let base_tx = base.clone().build().unwrap();
+    let variant_tx = variant.build().unwrap();
+    assert_eq!(1, base_tx.get_commit().get_transfers().len());
+    assert_eq!(2, variant_tx.get_commit().get_transfers().len());
+    assert!(variant_tx.verify_signatures(DEFAULT_CHAIN_ID).is_ok());
+
+    let debug = format!("{:?}", base);
+    assert!(debug.contains("<redacted>"));
+    assert!(!debug.contains(&sk1.to_base64()));
+}
+
+#[test]
+fn test_net_delta_overflow() {
+    use std::i64;
+
+    let (pk1, _) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let (pk3, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_unsigned_transfer(&pk1, &pk3, i64::MAX as u64, 0).unwrap()
+        .add_unsigned_transfer(&pk2, &pk3, 1, 0).unwrap();
+    let tx = tx_builder.build_unsigned().unwrap();
+    assert_eq!(Err(IroncError::token_overflow()), tx.net_delta());
+
+    let mut too_large = tx.clone();
+    too_large.mut_commit().mut_transfers()[1].set_tokens(i64::MAX as u64 + 1);
+    assert_eq!(Err(IroncError::token_overflow()), too_large.net_delta());
+}
+
+#[test]
+fn test_validate_structure() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap();
+    let tx = tx_builder.build().unwrap();
+    assert!(tx.validate_structure().is_ok());
+
+    // Signed again, so only the structural check fails.
+    let mut truncated = tx.clone();
+    truncated.mut_commit().mut_transfers()[0].set_destination_pk(
+        pk2.0[..3].to_vec());
+    let sign = sign(&sk1, &truncated.commit_bytes().unwrap());
+    truncated.clear_signatures();
+    truncated.attach_signature(&pk1, &sign);
+    let expected = Err(IroncError::new(
+        "Transfer 0 has an invalid destination public key: Invalid public \
+         key length 3 != 32 (required)"));
+    assert_eq!(expected, truncated.validate_structure());
+    assert_eq!(expected, truncated.verify_signatures(DEFAULT_CHAIN_ID));
+
+    let coinbase = TransactionBuilder::coinbase(&pk2, 5).unwrap();
+    assert!(coinbase.validate_structure().is_ok());
+}
+
+#[test]
+fn test_verify_signatures_with_policy() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap()
+        .set_bounty(&sk2, &pk2, 1);
+    let tx = tx_builder.build().unwrap();
+
+    let mut allowed = HashSet::new();
+    allowed.insert(pk1.clone());
+    assert_eq!(Err(IroncError::new(&format!(
+        "Signer {} isn't allowed to sign transactions.", pk2.to_address()))),
+        tx.verify_signatures_with_policy(DEFAULT_CHAIN_ID, &allowed));
+    allowed.insert(pk2.clone());
+    assert!(tx.verify_signatures_with_policy(DEFAULT_CHAIN_ID, &allowed).is_ok());
+
+    let mut unsigned = tx.clone();
+    unsigned.clear_signatures();
+    assert!(unsigned.verify_signatures_with_policy(
+        DEFAULT_CHAIN_ID, &allowed).is_err());
+}
+
+#[test]
+fn test_serialize_parse_transaction() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap();
+    let tx = tx_builder.build().unwrap();
+    let tx_bytes = serialize_transaction(&tx).unwrap();
+    assert_eq!(tx.serialized_size().unwrap(), tx_bytes.len());
+    assert_eq!(tx, parse_transaction(&tx_bytes).unwrap());
+    assert_eq!(IroncErrorKind::Protobuf,
+               parse_transaction(b"\xff\xff").unwrap_err().kind());
+}
+
+#[test]
+fn test_tx_id_dedup() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap()
+        .set_bounty(&sk2, &pk2, 1);
+    let tx = tx_builder.build().unwrap();
+    let mut reordered = tx.clone();
+    let mut signatures = tx.get_signatures().to_vec();
+    signatures.reverse();
+    reordered.set_signatures(::protobuf::RepeatedField::from_vec(signatures));
+    assert!(tx != reordered);
+
+    let mut other_builder = TransactionBuilder::new();
+    other_builder.add_transfer(&sk1, &pk1, &pk2, 11, 0).unwrap();
+    let other = other_builder.build().unwrap();
+
+    let mut seen = HashSet::new();
+    assert!(seen.insert(TxId::of(&tx).unwrap()));
+    assert!(!seen.insert(TxId::of(&reordered).unwrap()));
+    assert!(seen.insert(TxId::of(&other).unwrap()));
+    assert_eq!(tx.txid().unwrap().to_base64(base64::STANDARD),
+               format!("{}", TxId::of(&tx).unwrap()));
+}
+
+#[test]
+fn test_resign() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).unwrap()
+        .set_bounty(&sk2, &pk2, 1);
+    let mut tx = tx_builder.build().unwrap();
+
+    tx.mut_commit().set_bounty(3);
+    assert!(tx.verify_signatures(DEFAULT_CHAIN_ID).is_err());
+    let bumped = tx.clone();
+    assert!(tx.resign(&[(pk1.clone(), sk1.clone())]).is_err());
+    assert_eq!(bumped, tx);
+    assert!(tx.resign(&[(pk2.clone(), sk1.clone())]).is_err());
+
+    tx.resign(&[(pk1, sk1), (pk2, sk2)]).unwrap();
+    assert_eq!(3, tx.fee());
+    assert_eq!(2, tx.get_signatures().len());
+    assert!(tx.verify_signatures(DEFAULT_CHAIN_ID).is_ok());
+}
+
+#[test]
+fn test_verify_all() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let (pk3, sk3) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_unsigned_transfer(&pk1, &pk3, 10, 0).unwrap()
+        .add_unsigned_transfer(&pk2, &pk3, 5, 0).unwrap()
+        .set_unsigned_bounty(&pk2, 1);
+    let mut tx = tx_builder.build_unsigned().unwrap();
+    // Both transfers and the bounty are missing their signatures.
+    assert_eq!(3, tx.verify_all().len());
+
+    let commit_bytes = tx.commit_bytes().unwrap();
+    tx.attach_signature(&pk1, &sign(&sk1, &commit_bytes));
+    tx.attach_signature(&pk3, &sign(&sk3, &commit_bytes));
+    assert_eq!(vec![
+        VerificationIssue::Transfer(1, missing_signature_error(1, &pk2.0)),
+        VerificationIssue::Bounty(IroncError::missing_key(
+            "Missing signature for the bounty public key.")),
+        VerificationIssue::OrphanSignature(
+            orphan_signature_error(&tx.get_signatures()[1]))],
+        tx.verify_all());
+
+    let mut signatures = tx.get_signatures().to_vec();
+    signatures.pop();
+    tx.set_signatures(::protobuf::RepeatedField::from_vec(signatures));
+    tx.attach_signature(&pk2, &sign(&sk2, &commit_bytes));
+    assert!(tx.verify_all().is_empty());
+    assert!(tx.verify_signatures(DEFAULT_CHAIN_ID).is_ok());
+
+    tx.mut_commit().mut_transfers()[0].set_destination_pk(vec![1, 2, 3]);
+    let issues = tx.verify_all();
+    assert_eq!(VerificationIssue::Structure(IroncError::new(
+        "Transfer 0 has an invalid destination public key: Invalid public \
+         key length 3 != 32 (required)")), issues[0]);
+    assert!(issues.len() > 1);
+}